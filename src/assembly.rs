@@ -0,0 +1,488 @@
+// The text assembly. One instruction per line, `;` starts a comment:
+//
+//     .entry main
+//     .entry "Talk to Bob" L7
+//     .symbol main
+//     .symbol "Talk to Bob" L7
+//     .symbol helper
+//
+//     main:
+//         push_ptr L3
+//         jmp L7
+//         msg "Bye"
+//     L3:
+//         ret
+//     L7:
+//         choose
+//             option "Hi" helper once
+//         end
+//
+//     helper:
+//         ret
+//
+// The jump targets are labels, but plain addresses are accepted too.
+// The names and the strings which aren't identifiers are quoted.
+// The `.symbol`s name all the dialogues, the private ones too.
+
+use crate::vm::{ Instruction, BranchLeaf, TemplatePiece, Value };
+use crate::linker::Executable;
+use crate::opcode_saver::{ bin_op_name, un_op_name };
+use crate::opcode_loader::{ parse_bin_op_name, parse_un_op_name };
+
+use std::fmt;
+use std::error::Error;
+use std::collections::{ BTreeMap, HashMap };
+
+use linked_hash_map::LinkedHashMap;
+
+/// A malformed line of the assembly. The line starts from 1
+#[derive(Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line : usize,
+    pub message : String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+fn is_ident(s : &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_') &&
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_') &&
+    !matches!(s, "true" | "false")
+}
+
+// The disassembler names the labels `L<address>`, so the
+// entry points can't take such names
+fn is_generated_label(s : &str) -> bool {
+    s.len() > 1 && s.starts_with('L') && s[1..].chars().all(|c| c.is_ascii_digit())
+}
+
+fn quote(s : &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            '\r' => res.push_str("\\r"),
+            _ => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+fn name(s : &str) -> String {
+    if is_ident(s) { s.to_string() } else { quote(s) }
+}
+
+fn value(x : &Value) -> String {
+    match x {
+        Value::Int(x) => x.to_string(),
+        Value::Bool(x) => x.to_string(),
+        Value::Str(x) => quote(x),
+    }
+}
+
+/// Writes the executable as text assembly
+pub fn disassemble(exe : &Executable) -> String {
+    let len = exe.opcodes.len();
+    let mut labels = BTreeMap::new();
+    for (entry, address) in exe.entry_points.iter().chain(exe.symbols.iter()) {
+        if is_ident(entry) && !is_generated_label(entry) && *address <= len {
+            labels.entry(*address).or_insert_with(|| entry.clone());
+        }
+    }
+    let targets =
+        exe.opcodes.iter()
+        .flat_map(
+            |x| {
+                match x {
+                    Instruction::Jmp(x) | Instruction::JmpIfNot(x) | Instruction::PushPtr(x) => vec![*x],
+                    Instruction::Branch(leaves) => leaves.iter().map(|x| x.jmp_address).collect(),
+                    _ => Vec::new(),
+                }
+            }
+        )
+    ;
+    let entries = exe.entry_points.values().chain(exe.symbols.values()).copied();
+    for address in entries.chain(targets).filter(|x| *x <= len) {
+        labels.entry(address).or_insert_with(|| format!("L{}", address));
+    }
+    // The addresses past the end can't be labeled
+    let target = |x : usize| labels.get(&x).cloned().unwrap_or_else(|| x.to_string());
+
+    let mut out = String::new();
+    let directives = exe.entry_points.iter().map(|x| (".entry", x)).chain(exe.symbols.iter().map(|x| (".symbol", x)));
+    for (directive, (entry, address)) in directives {
+        let label = target(*address);
+        if label == *entry { out.push_str(&format!("{} {}\n", directive, label)); }
+        else { out.push_str(&format!("{} {} {}\n", directive, name(entry), label)); }
+    }
+    for (i, opcode) in exe.opcodes.iter().enumerate() {
+        if let Some(label) = labels.get(&i) { out.push_str(&format!("\n{}:\n", label)); }
+        let line = {
+            match opcode {
+                Instruction::Ret => "ret".to_string(),
+                Instruction::Wait => "wait".to_string(),
+                Instruction::Jmp(x) => format!("jmp {}", target(*x)),
+                Instruction::JmpIfNot(x) => format!("jmp_if_not {}", target(*x)),
+                Instruction::PushPtr(x) => format!("push_ptr {}", target(*x)),
+                Instruction::Msg(x) => format!("msg {}", quote(x)),
+                Instruction::Template(pieces) => {
+                    let pieces : Vec<_> =
+                        pieces.iter()
+                        .map(
+                            |x| {
+                                match x {
+                                    TemplatePiece::Text(x) => quote(x),
+                                    TemplatePiece::Value => "$".to_string(),
+                                }
+                            }
+                        )
+                        .collect()
+                    ;
+                    format!("template {}", pieces.join(" "))
+                },
+                Instruction::Push(x) => format!("push {}", value(x)),
+                Instruction::Load(x) => format!("load {}", name(x)),
+                Instruction::Store(x) => format!("store {}", name(x)),
+                Instruction::BinOp(x) => format!("bin_op {}", bin_op_name(*x)),
+                Instruction::UnOp(x) => format!("un_op {}", un_op_name(*x)),
+                Instruction::Branch(leaves) => {
+                    let mut res = "choose\n".to_string();
+                    for leaf in leaves.iter() {
+                        res.push_str(&format!("        option {} {}", quote(&leaf.option_name), target(leaf.jmp_address)));
+                        if leaf.conditional { res.push_str(" conditional"); }
+                        if leaf.once { res.push_str(" once"); }
+                        if leaf.fallback { res.push_str(" fallback"); }
+                        res.push('\n');
+                    }
+                    res.push_str("    end");
+                    res
+                },
+            }
+        };
+        out.push_str(&format!("    {}\n", line));
+    }
+    if let Some(label) = labels.get(&len) { out.push_str(&format!("\n{}:\n", label)); }
+    out
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Int(i64),
+    Str(String),
+    Dollar,
+    Colon,
+}
+
+fn tokenize_line(line : &str) -> Result<Vec<Token>, String> {
+    let chars : Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        match c {
+            ';' => break,
+            _ if c.is_whitespace() => { i += 1; },
+            '$' => { tokens.push(Token::Dollar); i += 1; },
+            ':' => { tokens.push(Token::Colon); i += 1; },
+            '"' => {
+                let mut s = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err("the string is never closed".to_string()),
+                        Some('"') => { i += 1; break },
+                        Some('\\') => {
+                            match chars.get(i + 1) {
+                                Some('n') => s.push('\n'),
+                                Some('t') => s.push('\t'),
+                                Some('r') => s.push('\r'),
+                                Some('"') => s.push('"'),
+                                Some('\\') => s.push('\\'),
+                                _ => return Err("unknown escape sequence".to_string()),
+                            }
+                            i += 2;
+                        },
+                        Some(x) => { s.push(*x); i += 1; },
+                    }
+                }
+                tokens.push(Token::Str(s));
+            },
+            '-' | '0'..='9' => {
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_digit() { i += 1; }
+                let digits : String = chars[start..i].iter().collect();
+                match digits.parse() {
+                    Ok(x) => tokens.push(Token::Int(x)),
+                    Err(_) => return Err(format!("bad number \"{}\"", digits)),
+                }
+            },
+            _ if c.is_ascii_alphabetic() || c == '_' || c == '.' => {
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '.') { i += 1; }
+                tokens.push(Token::Word(chars[start..i].iter().collect()));
+            },
+            _ => return Err(format!("unexpected character `{}`", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+// Where an entry point leads
+enum EntryTarget {
+    Label(String),
+    Address(usize),
+}
+
+// A jump target which is resolved once all the labels are known
+struct Fixup {
+    at : usize,
+    leaf : Option<usize>,
+    label : String,
+    line : usize,
+}
+
+struct Assembler {
+    opcodes : Vec<Instruction>,
+    labels : HashMap<String, usize>,
+    // (name, target, line)
+    entries : Vec<(String, EntryTarget, usize)>,
+    symbols : Vec<(String, EntryTarget, usize)>,
+    fixups : Vec<Fixup>,
+    // the choice which is being assembled
+    choice : Option<Vec<BranchLeaf>>,
+    line : usize,
+}
+
+impl Assembler {
+    fn error<T>(&self, message : String) -> Result<T, AsmError> {
+        Err(AsmError { line : self.line, message })
+    }
+
+    // Either a label or a plain address
+    fn target(&mut self, token : Option<&Token>, leaf : Option<usize>) -> Result<usize, AsmError> {
+        match token {
+            Some(Token::Int(x)) if *x >= 0 => Ok(*x as usize),
+            Some(Token::Word(x)) => {
+                self.fixups.push(Fixup { at : self.opcodes.len(), leaf, label : x.clone(), line : self.line });
+                Ok(0)
+            },
+            _ => self.error("expected a label or an address".to_string()),
+        }
+    }
+
+    fn name(&self, token : Option<&Token>) -> Result<String, AsmError> {
+        match token {
+            Some(Token::Word(x)) | Some(Token::Str(x)) => Ok(x.clone()),
+            _ => self.error("expected a name".to_string()),
+        }
+    }
+
+    fn value(&self, token : Option<&Token>) -> Result<Value, AsmError> {
+        match token {
+            Some(Token::Int(x)) => Ok(Value::Int(*x)),
+            Some(Token::Str(x)) => Ok(Value::Str(x.clone())),
+            Some(Token::Word(x)) if x == "true" => Ok(Value::Bool(true)),
+            Some(Token::Word(x)) if x == "false" => Ok(Value::Bool(false)),
+            _ => self.error("expected a value".to_string()),
+        }
+    }
+
+    fn option(&mut self, tokens : &[Token]) -> Result<BranchLeaf, AsmError> {
+        let option_name = {
+            match tokens.first() {
+                Some(Token::Str(x)) => x.clone(),
+                _ => return self.error("expected the option's name".to_string()),
+            }
+        };
+        let leaf_id = self.choice.as_ref().map(|x| x.len());
+        let jmp_address = self.target(tokens.get(1), leaf_id)?;
+        let mut leaf = BranchLeaf { option_name, jmp_address, conditional : false, once : false, fallback : false };
+        for flag in tokens.iter().skip(2) {
+            match flag {
+                Token::Word(x) if x == "conditional" => { leaf.conditional = true; },
+                Token::Word(x) if x == "once" => { leaf.once = true; },
+                Token::Word(x) if x == "fallback" => { leaf.fallback = true; },
+                _ => return self.error("unknown option flag".to_string()),
+            }
+        }
+        Ok(leaf)
+    }
+
+    fn expect_end(&self, tokens : &[Token], count : usize) -> Result<(), AsmError> {
+        if tokens.len() > count { return self.error("unexpected tokens at the end of the line".to_string()) }
+        Ok(())
+    }
+
+    fn line(&mut self, tokens : Vec<Token>) -> Result<(), AsmError> {
+        let (first, rest) = {
+            match tokens.split_first() {
+                Some((Token::Word(x), rest)) => (x.as_str(), rest),
+                Some(_) => return self.error("expected an instruction, a label or a directive".to_string()),
+                None => return Ok(()),
+            }
+        };
+
+        // Inside of a choice only the options are allowed
+        if self.choice.is_some() {
+            match first {
+                "option" => {
+                    let leaf = self.option(rest)?;
+                    self.choice.as_mut().unwrap().push(leaf);
+                },
+                "end" => {
+                    self.expect_end(rest, 0)?;
+                    let leaves = self.choice.take().unwrap();
+                    self.opcodes.push(Instruction::Branch(leaves));
+                },
+                _ => return self.error("expected `option` or `end`".to_string()),
+            }
+            return Ok(())
+        }
+
+        if rest.first() == Some(&Token::Colon) {
+            self.expect_end(rest, 1)?;
+            if self.labels.insert(first.to_string(), self.opcodes.len()).is_some() {
+                return self.error(format!("the label \"{}\" is defined twice", first))
+            }
+            return Ok(())
+        }
+
+        let opcode = {
+            match first {
+                ".entry" | ".symbol" => {
+                    let entry = self.name(rest.first())?;
+                    let label = {
+                        match rest.get(1) {
+                            Some(Token::Word(x)) => EntryTarget::Label(x.clone()),
+                            Some(Token::Int(x)) if *x >= 0 => EntryTarget::Address(*x as usize),
+                            None => EntryTarget::Label(entry.clone()),
+                            _ => return self.error("expected a label or an address".to_string()),
+                        }
+                    };
+                    self.expect_end(rest, 2)?;
+                    let line = self.line;
+                    if first == ".entry" { self.entries.push((entry, label, line)); }
+                    else { self.symbols.push((entry, label, line)); }
+                    return Ok(())
+                },
+                "choose" => {
+                    self.expect_end(rest, 0)?;
+                    self.choice = Some(Vec::new());
+                    return Ok(())
+                },
+                "ret" => { self.expect_end(rest, 0)?; Instruction::Ret },
+                "wait" => { self.expect_end(rest, 0)?; Instruction::Wait },
+                "jmp" => { self.expect_end(rest, 1)?; Instruction::Jmp(self.target(rest.first(), None)?) },
+                "jmp_if_not" => { self.expect_end(rest, 1)?; Instruction::JmpIfNot(self.target(rest.first(), None)?) },
+                "push_ptr" => { self.expect_end(rest, 1)?; Instruction::PushPtr(self.target(rest.first(), None)?) },
+                "msg" => {
+                    self.expect_end(rest, 1)?;
+                    match rest.first() {
+                        Some(Token::Str(x)) => Instruction::Msg(x.clone()),
+                        _ => return self.error("expected a string".to_string()),
+                    }
+                },
+                "template" => {
+                    let pieces =
+                        rest.iter()
+                        .map(
+                            |x| {
+                                match x {
+                                    Token::Str(x) => Ok(TemplatePiece::Text(x.clone())),
+                                    Token::Dollar => Ok(TemplatePiece::Value),
+                                    _ => self.error("expected a string or `$`".to_string()),
+                                }
+                            }
+                        )
+                        .collect::<Result<_, _>>()?
+                    ;
+                    Instruction::Template(pieces)
+                },
+                "push" => { self.expect_end(rest, 1)?; Instruction::Push(self.value(rest.first())?) },
+                "load" => { self.expect_end(rest, 1)?; Instruction::Load(self.name(rest.first())?) },
+                "store" => { self.expect_end(rest, 1)?; Instruction::Store(self.name(rest.first())?) },
+                "bin_op" => {
+                    self.expect_end(rest, 1)?;
+                    match rest.first() {
+                        Some(Token::Word(x)) if parse_bin_op_name(x).is_some() => Instruction::BinOp(parse_bin_op_name(x).unwrap()),
+                        _ => return self.error("expected an operator".to_string()),
+                    }
+                },
+                "un_op" => {
+                    self.expect_end(rest, 1)?;
+                    match rest.first() {
+                        Some(Token::Word(x)) if parse_un_op_name(x).is_some() => Instruction::UnOp(parse_un_op_name(x).unwrap()),
+                        _ => return self.error("expected an operator".to_string()),
+                    }
+                },
+                _ => return self.error(format!("unknown instruction \"{}\"", first)),
+            }
+        };
+        self.opcodes.push(opcode);
+        Ok(())
+    }
+}
+
+/// Reads the text assembly written by `disassemble`
+pub fn assemble(src : &str) -> Result<Executable, AsmError> {
+    let mut asm = Assembler {
+        opcodes : Vec::new(),
+        labels : HashMap::new(),
+        entries : Vec::new(),
+        symbols : Vec::new(),
+        fixups : Vec::new(),
+        choice : None,
+        line : 0,
+    };
+    for (i, line) in src.lines().enumerate() {
+        asm.line = i + 1;
+        let tokens = tokenize_line(line).map_err(|message| AsmError { line : i + 1, message })?;
+        asm.line(tokens)?;
+    }
+    if asm.choice.is_some() { return asm.error("the `choose` is never closed with `end`".to_string()) }
+
+    let Assembler { mut opcodes, labels, entries, symbols, fixups, .. } = asm;
+    let resolve = |label : &str, line : usize| {
+        labels.get(label).copied().ok_or_else(|| AsmError { line, message : format!("unknown label \"{}\"", label) })
+    };
+    for Fixup { at, leaf, label, line } in fixups.into_iter() {
+        let address = resolve(&label, line)?;
+        match (&mut opcodes[at], leaf) {
+            (Instruction::Jmp(x), None) | (Instruction::JmpIfNot(x), None) | (Instruction::PushPtr(x), None) => { *x = address; },
+            // The choice is pushed after its options are read
+            (Instruction::Branch(leaves), Some(leaf)) => { leaves[leaf].jmp_address = address; },
+            _ => unreachable!(),
+        }
+    }
+    let table = |directives : Vec<(String, EntryTarget, usize)>, what : &str| {
+        let mut res = LinkedHashMap::new();
+        for (entry, label, line) in directives.into_iter() {
+            let address = {
+                match label {
+                    EntryTarget::Label(label) => resolve(&label, line)?,
+                    EntryTarget::Address(address) => address,
+                }
+            };
+            if res.insert(entry.clone(), address).is_some() {
+                return Err(AsmError { line, message : format!("the {} \"{}\" is defined twice", what, entry) })
+            }
+        }
+        Ok(res)
+    };
+    let entry_points = table(entries, "entry point")?;
+    let symbols = table(symbols, "symbol")?;
+    Ok(Executable { entry_points, symbols, opcodes })
+}
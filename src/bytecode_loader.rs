@@ -0,0 +1,181 @@
+use crate::vm::{ Instruction, BranchLeaf, BinOp, UnOp, TemplatePiece, Value };
+use crate::linker::Executable;
+use crate::error::LoadError;
+use crate::bytecode_saver::*;
+
+use std::convert::TryInto;
+
+use linked_hash_map::LinkedHashMap;
+
+fn parse_bin_op_code(code : u8) -> Option<BinOp> {
+    match code {
+        0 => Some(BinOp::Add),
+        1 => Some(BinOp::Sub),
+        2 => Some(BinOp::Mul),
+        3 => Some(BinOp::Div),
+        4 => Some(BinOp::Rem),
+        5 => Some(BinOp::Eq),
+        6 => Some(BinOp::Ne),
+        7 => Some(BinOp::Lt),
+        8 => Some(BinOp::Le),
+        9 => Some(BinOp::Gt),
+        10 => Some(BinOp::Ge),
+        _ => None,
+    }
+}
+
+fn parse_un_op_code(code : u8) -> Option<UnOp> {
+    match code {
+        0 => Some(UnOp::Not),
+        1 => Some(UnOp::Neg),
+        _ => None,
+    }
+}
+
+/// Tells if the bytes look like the binary container
+pub fn is_bytecode(bytes : &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+// Reads the container from the start to the end. Every read
+// fails with `Truncated` if the bytes run out
+struct Reader<'a> {
+    bytes : &'a [u8],
+    cursor : usize,
+    strings : Vec<String>,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len : usize) -> Result<&'a [u8], LoadError> {
+        let end = self.cursor.checked_add(len).filter(|x| *x <= self.bytes.len()).ok_or(LoadError::Truncated)?;
+        let res = &self.bytes[self.cursor..end];
+        self.cursor = end;
+        Ok(res)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> { Ok(self.take(1)?[0]) }
+
+    fn u16(&mut self) -> Result<u16, LoadError> { Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap())) }
+
+    fn u32(&mut self) -> Result<usize, LoadError> { Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize) }
+
+    fn i64(&mut self) -> Result<i64, LoadError> { Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap())) }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let id = self.u32()?;
+        self.strings.get(id).cloned().ok_or(LoadError::BadString(id))
+    }
+
+    // The entry table and the symbol table
+    fn address_table(&mut self) -> Result<LinkedHashMap<String, usize>, LoadError> {
+        let count = self.u32()?;
+        (0..count)
+        .map(|_| Ok((self.string()?, self.u32()?)))
+        .collect()
+    }
+
+    // `None` means that the value is malformed
+    fn value(&mut self) -> Result<Option<Value>, LoadError> {
+        match self.u8()? {
+            INT => Ok(Some(Value::Int(self.i64()?))),
+            BOOL => Ok(Some(Value::Bool(self.u8()? != 0))),
+            STR => Ok(Some(Value::Str(self.string()?))),
+            _ => Ok(None),
+        }
+    }
+
+    // `None` means that the opcode is malformed
+    fn opcode(&mut self) -> Result<Option<Instruction>, LoadError> {
+        let opcode = {
+            match self.u8()? {
+                RET => Instruction::Ret,
+                JMP => Instruction::Jmp(self.u32()?),
+                JMP_IF_NOT => Instruction::JmpIfNot(self.u32()?),
+                MSG => Instruction::Msg(self.string()?),
+                TEMPLATE => {
+                    let count = self.u32()?;
+                    let mut pieces = Vec::new();
+                    for _ in 0..count {
+                        match self.u8()? {
+                            TEXT_PIECE => pieces.push(TemplatePiece::Text(self.string()?)),
+                            VALUE_PIECE => pieces.push(TemplatePiece::Value),
+                            _ => return Ok(None),
+                        }
+                    }
+                    Instruction::Template(pieces)
+                },
+                WAIT => Instruction::Wait,
+                BRANCH => {
+                    let count = self.u32()?;
+                    let mut leaves = Vec::new();
+                    for _ in 0..count {
+                        let option_name = self.string()?;
+                        let jmp_address = self.u32()?;
+                        let flags = self.u8()?;
+                        leaves.push(
+                            BranchLeaf {
+                                option_name,
+                                jmp_address,
+                                conditional : flags & CONDITIONAL != 0,
+                                once : flags & ONCE != 0,
+                                fallback : flags & FALLBACK != 0,
+                            }
+                        );
+                    }
+                    Instruction::Branch(leaves)
+                },
+                PUSH_PTR => Instruction::PushPtr(self.u32()?),
+                PUSH => {
+                    match self.value()? {
+                        Some(x) => Instruction::Push(x),
+                        None => return Ok(None),
+                    }
+                },
+                LOAD => Instruction::Load(self.string()?),
+                BIN_OP => {
+                    match parse_bin_op_code(self.u8()?) {
+                        Some(x) => Instruction::BinOp(x),
+                        None => return Ok(None),
+                    }
+                },
+                UN_OP => {
+                    match parse_un_op_code(self.u8()?) {
+                        Some(x) => Instruction::UnOp(x),
+                        None => return Ok(None),
+                    }
+                },
+                STORE => Instruction::Store(self.string()?),
+                _ => return Ok(None),
+            }
+        };
+        Ok(Some(opcode))
+    }
+}
+
+pub fn parse_bytecode_executable(bytes : &[u8]) -> Result<Executable, LoadError> {
+    if !is_bytecode(bytes) { return Err(LoadError::NotBytecode) }
+    let mut reader = Reader { bytes, cursor : MAGIC.len(), strings : Vec::new() };
+
+    let version = reader.u16()?;
+    if version != VERSION && version != 1 { return Err(LoadError::UnsupportedVersion(version)) }
+
+    let string_count = reader.u32()?;
+    for i in 0..string_count {
+        let len = reader.u32()?;
+        let s = std::str::from_utf8(reader.take(len)?).map_err(|_| LoadError::BadString(i))?;
+        reader.strings.push(s.to_string());
+    }
+
+    let entry_points = reader.address_table()?;
+
+    let opcode_count = reader.u32()?;
+    let opcodes =
+        (0..opcode_count)
+        .map(|i| reader.opcode()?.ok_or(LoadError::BadOpcode(i)))
+        .collect::<Result<_, _>>()?
+    ;
+    let symbols = if version == 1 { Default::default() } else { reader.address_table()? };
+    // a longer file is most likely broken or not ours at all
+    if reader.cursor != bytes.len() { return Err(LoadError::TrailingBytes(reader.cursor)) }
+    Ok(Executable { entry_points, symbols, opcodes })
+}
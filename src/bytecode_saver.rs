@@ -0,0 +1,198 @@
+// The binary container. Everything is little-endian:
+//
+//   magic           b"TXAD"
+//   version         u16
+//   string table    u32 count, then u32 length + UTF-8 bytes for each string
+//   entry table     u32 count, then u32 name + u32 address for each entry
+//   opcodes         u32 count, then u8 tag + operands for each opcode
+//   symbol table    u32 count, then u32 name + u32 address for each dialogue
+//
+// The version 1 files end with the opcodes.
+// Strings are written once and referred to by their u32 index.
+// Addresses and counts are u32 too.
+
+use crate::vm::{ Instruction, BinOp, UnOp, TemplatePiece, Value };
+use crate::linker::Executable;
+
+use std::collections::HashMap;
+
+pub const MAGIC : &[u8; 4] = b"TXAD";
+pub const VERSION : u16 = 2;
+
+// Opcode tags
+pub const RET : u8 = 0;
+pub const JMP : u8 = 1;
+pub const JMP_IF_NOT : u8 = 2;
+pub const MSG : u8 = 3;
+pub const TEMPLATE : u8 = 4;
+pub const WAIT : u8 = 5;
+pub const BRANCH : u8 = 6;
+pub const PUSH_PTR : u8 = 7;
+pub const PUSH : u8 = 8;
+pub const LOAD : u8 = 9;
+pub const BIN_OP : u8 = 10;
+pub const UN_OP : u8 = 11;
+pub const STORE : u8 = 12;
+
+// Value tags
+pub const INT : u8 = 0;
+pub const BOOL : u8 = 1;
+pub const STR : u8 = 2;
+
+// Template piece tags
+pub const TEXT_PIECE : u8 = 0;
+pub const VALUE_PIECE : u8 = 1;
+
+// Branch leaf flags
+pub const CONDITIONAL : u8 = 1;
+pub const ONCE : u8 = 2;
+pub const FALLBACK : u8 = 4;
+
+pub fn bin_op_code(op : BinOp) -> u8 {
+    match op {
+        BinOp::Add => 0,
+        BinOp::Sub => 1,
+        BinOp::Mul => 2,
+        BinOp::Div => 3,
+        BinOp::Rem => 4,
+        BinOp::Eq => 5,
+        BinOp::Ne => 6,
+        BinOp::Lt => 7,
+        BinOp::Le => 8,
+        BinOp::Gt => 9,
+        BinOp::Ge => 10,
+    }
+}
+
+pub fn un_op_code(op : UnOp) -> u8 {
+    match op {
+        UnOp::Not => 0,
+        UnOp::Neg => 1,
+    }
+}
+
+// Collects the strings, so each of them is written once
+struct StringTable<'a> {
+    ids : HashMap<&'a str, u32>,
+    strings : Vec<&'a str>,
+}
+
+impl<'a> StringTable<'a> {
+    fn intern(&mut self, s : &'a str) -> u32 {
+        let strings = &mut self.strings;
+        *self.ids.entry(s).or_insert_with(
+            || {
+                strings.push(s);
+                (strings.len() - 1) as u32
+            }
+        )
+    }
+}
+
+fn put_u32(out : &mut Vec<u8>, x : usize) {
+    out.extend_from_slice(&(x as u32).to_le_bytes());
+}
+
+fn put_str<'a>(out : &mut Vec<u8>, table : &mut StringTable<'a>, s : &'a str) {
+    let id = table.intern(s);
+    out.extend_from_slice(&id.to_le_bytes());
+}
+
+fn put_value<'a>(out : &mut Vec<u8>, table : &mut StringTable<'a>, value : &'a Value) {
+    match value {
+        Value::Int(x) => { out.push(INT); out.extend_from_slice(&x.to_le_bytes()); },
+        Value::Bool(x) => { out.push(BOOL); out.push(*x as u8); },
+        Value::Str(x) => { out.push(STR); put_str(out, table, x); },
+    }
+}
+
+fn put_opcode<'a>(out : &mut Vec<u8>, table : &mut StringTable<'a>, opcode : &'a Instruction) {
+    match opcode {
+        Instruction::Ret => out.push(RET),
+        Instruction::Jmp(x) => { out.push(JMP); put_u32(out, *x); },
+        Instruction::JmpIfNot(x) => { out.push(JMP_IF_NOT); put_u32(out, *x); },
+        Instruction::Msg(x) => { out.push(MSG); put_str(out, table, x); },
+        Instruction::Template(pieces) => {
+            out.push(TEMPLATE);
+            put_u32(out, pieces.len());
+            for piece in pieces.iter() {
+                match piece {
+                    TemplatePiece::Text(x) => { out.push(TEXT_PIECE); put_str(out, table, x); },
+                    TemplatePiece::Value => out.push(VALUE_PIECE),
+                }
+            }
+        },
+        Instruction::Wait => out.push(WAIT),
+        Instruction::Branch(leaves) => {
+            out.push(BRANCH);
+            put_u32(out, leaves.len());
+            for leaf in leaves.iter() {
+                put_str(out, table, &leaf.option_name);
+                put_u32(out, leaf.jmp_address);
+                let flags =
+                    if leaf.conditional { CONDITIONAL } else { 0 } |
+                    if leaf.once { ONCE } else { 0 } |
+                    if leaf.fallback { FALLBACK } else { 0 }
+                ;
+                out.push(flags);
+            }
+        },
+        Instruction::PushPtr(x) => { out.push(PUSH_PTR); put_u32(out, *x); },
+        Instruction::Push(x) => { out.push(PUSH); put_value(out, table, x); },
+        Instruction::Load(x) => { out.push(LOAD); put_str(out, table, x); },
+        Instruction::BinOp(x) => { out.push(BIN_OP); out.push(bin_op_code(*x)); },
+        Instruction::UnOp(x) => { out.push(UN_OP); out.push(un_op_code(*x)); },
+        Instruction::Store(x) => { out.push(STORE); put_str(out, table, x); },
+    }
+}
+
+pub fn executable_into_bytes(exe : &Executable) -> Vec<u8> {
+    let mut table = StringTable { ids : HashMap::new(), strings : Vec::new() };
+
+    // The string table goes first, so the rest is written aside
+    let mut body = Vec::new();
+    put_u32(&mut body, exe.entry_points.len());
+    for (name, address) in exe.entry_points.iter() {
+        put_str(&mut body, &mut table, name);
+        put_u32(&mut body, *address);
+    }
+    put_u32(&mut body, exe.opcodes.len());
+    for opcode in exe.opcodes.iter() {
+        put_opcode(&mut body, &mut table, opcode);
+    }
+    put_u32(&mut body, exe.symbols.len());
+    for (name, address) in exe.symbols.iter() {
+        put_str(&mut body, &mut table, name);
+        put_u32(&mut body, *address);
+    }
+
+    let mut out = Vec::with_capacity(body.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    put_string_table(&mut out, &table);
+    out.extend_from_slice(&body);
+    out
+}
+
+fn put_string_table(out : &mut Vec<u8>, table : &StringTable) {
+    put_u32(out, table.strings.len());
+    for s in table.strings.iter() {
+        put_u32(out, s.len());
+        out.extend_from_slice(s.as_bytes());
+    }
+}
+
+/// The opcodes the way the container writes them, together with
+/// their own string table. The program's fingerprint is made from them
+pub fn opcodes_into_bytes(opcodes : &[Instruction]) -> Vec<u8> {
+    let mut table = StringTable { ids : HashMap::new(), strings : Vec::new() };
+    let mut body = Vec::new();
+    put_u32(&mut body, opcodes.len());
+    for opcode in opcodes.iter() {
+        put_opcode(&mut body, &mut table, opcode);
+    }
+    let mut out = Vec::with_capacity(body.len());
+    put_string_table(&mut out, &table);
+    out.extend_from_slice(&body);
+    out
+}
@@ -0,0 +1,163 @@
+// The checks which run without running the story. Unlike the compiler,
+// which stops at the first error, the checker collects all of them
+
+use crate::parser::{ Ast, AstKind, File, variable_usages, visit_code };
+use crate::translator::{ call_target, qualified_name };
+use crate::error::{ CompileError, CompileErrorKind };
+use crate::marked_yaml::Pos;
+
+use std::collections::{ HashMap, HashSet, VecDeque };
+
+use linked_hash_map::LinkedHashMap;
+
+// The file which defines the dialogue, whether it's `pub`
+// and where its name was written
+struct Symbol {
+    file : usize,
+    public : bool,
+    pos : Pos,
+}
+
+// The calls which happen every time the dialogue runs, before the
+// player gets any choice. The calls inside of `if`s may not happen
+fn unconditional_calls(code : &[Ast]) -> Vec<&str> {
+    let mut res = Vec::new();
+    for ast in code.iter() {
+        match &ast.kind {
+            AstKind::Call(x) => res.push(x.as_str()),
+            AstKind::Choice(_) => break,
+            _ => (),
+        }
+    }
+    res
+}
+
+// Looks for a chain of calls which leads from the dialogue back to it
+fn find_cycle(start : &str, calls : &HashMap<String, Vec<String>>) -> Option<Vec<String>> {
+    let mut stack = vec![vec![start.to_string()]];
+    let mut visited = HashSet::new();
+    while let Some(chain) = stack.pop() {
+        for next in calls.get(chain.last().unwrap()).into_iter().flatten() {
+            let mut longer = chain.clone();
+            longer.push(next.clone());
+            if next == start { return Some(longer) }
+            if visited.insert(next.clone()) { stack.push(longer) }
+        }
+    }
+    None
+}
+
+/// Checks the files of the story, which make the modules with the given
+/// names. Finds the problems the linker would report and the ones which
+/// would only show up while playing: the choices without options, the
+/// options with the same name, the dialogues which can't be reached from
+/// `main` and the dialogues which call each other forever. Every problem
+/// comes with the index of the file it was found in. The broken modules
+/// are the ones whose files couldn't be read, so nothing is known about
+/// their dialogues and the calls into them aren't reported
+pub fn check(files : &[File], modules : &[String], broken : &[String]) -> Vec<(usize, CompileError)> {
+    let mut problems = Vec::new();
+    let start = Pos { line : 1, col : 1 };
+
+    // the files without a name aren't modules
+    for (id, module) in modules.iter().enumerate() {
+        if !module.is_empty() && modules[..id].contains(module) {
+            problems.push((id, CompileError::new(CompileErrorKind::DuplicateModule(module.clone()), start)));
+        }
+    }
+
+    let mut symbols = LinkedHashMap::new();
+    for (id, file) in files.iter().enumerate() {
+        for (name, proc) in file.procs.iter() {
+            let full_name = qualified_name(&modules[id], name);
+            if symbols.contains_key(&full_name) {
+                problems.push((id, CompileError::new(CompileErrorKind::DuplicateProc(full_name), proc.pos)));
+            } else {
+                symbols.insert(full_name, Symbol { file : id, public : proc.public, pos : proc.pos });
+            }
+        }
+    }
+
+    let usages = files.iter().map(variable_usages).collect::<Vec<_>>();
+    let declared = usages.iter().flat_map(|(x, _)| x.iter()).collect::<HashSet<_>>();
+    for (id, (_, reads)) in usages.iter().enumerate() {
+        for (name, pos) in reads.iter().filter(|(x, _)| !declared.contains(x)) {
+            problems.push((id, CompileError::new(CompileErrorKind::UndeclaredVariable(name.clone()), *pos)));
+        }
+    }
+
+    // every call which can happen and the ones which happen for sure
+    let mut calls = HashMap::new();
+    let mut sure_calls = HashMap::new();
+    for (id, file) in files.iter().enumerate() {
+        for (name, proc) in file.procs.iter() {
+            let full_name = qualified_name(&modules[id], name);
+            let mut targets = Vec::new();
+            visit_code(
+                &proc.code,
+                &mut |ast| {
+                    match &ast.kind {
+                        AstKind::Call(x) => {
+                            let target = call_target(&modules[id], x);
+                            match symbols.get(&target) {
+                                Some(symbol) if symbol.public || symbol.file == id => targets.push(target),
+                                Some(_) => problems.push((id, CompileError::new(CompileErrorKind::PrivateDialogue(target), ast.pos))),
+                                None if target.split_once("::").is_some_and(|(x, _)| broken.iter().any(|y| y == x)) => (),
+                                None => problems.push((id, CompileError::new(CompileErrorKind::UnknownDialogue(target), ast.pos))),
+                            }
+                        },
+                        AstKind::Choice(options) if options.is_empty() =>
+                            problems.push((id, CompileError::new(CompileErrorKind::EmptyChoice, ast.pos))),
+                        AstKind::Choice(options) => {
+                            for (i, option) in options.iter().enumerate() {
+                                if options[..i].iter().any(|x| x.name == option.name) {
+                                    problems.push((id, CompileError::new(CompileErrorKind::DuplicateOption(option.name.clone()), option.pos)));
+                                }
+                            }
+                        },
+                        _ => (),
+                    }
+                }
+            );
+            let sure_targets =
+                unconditional_calls(&proc.code).into_iter()
+                .map(|x| call_target(&modules[id], x))
+                .filter(|x| targets.contains(x))
+                .collect::<Vec<_>>()
+            ;
+            // a second definition is already reported, and the
+            // calls of the first one are the ones which count
+            if symbols[&full_name].file != id { continue }
+            calls.insert(full_name.clone(), targets);
+            sure_calls.insert(full_name, sure_targets);
+        }
+    }
+
+    // a story without `main` is only a part of some other story. The
+    // broken files may call anything, so nothing is surely unreachable
+    if symbols.contains_key("main") && broken.is_empty() {
+        let mut reached = HashSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back("main".to_string());
+        while let Some(name) = queue.pop_front() {
+            if !reached.insert(name.clone()) { continue }
+            queue.extend(calls.get(&name).into_iter().flatten().cloned());
+        }
+        for (name, symbol) in symbols.iter().filter(|(x, _)| !reached.contains(*x)) {
+            problems.push((symbol.file, CompileError::new(CompileErrorKind::UnreachableDialogue(name.clone()), symbol.pos)));
+        }
+    }
+
+    // every cycle is reported once, at the first dialogue in it
+    let mut in_cycles = HashSet::new();
+    for (name, symbol) in symbols.iter() {
+        if in_cycles.contains(name) { continue }
+        if let Some(chain) = find_cycle(name, &sure_calls) {
+            in_cycles.extend(chain.iter().cloned());
+            problems.push((symbol.file, CompileError::new(CompileErrorKind::EndlessRecursion(chain), symbol.pos)));
+        }
+    }
+
+    problems.sort_by_key(|(id, e)| (*id, e.pos.line, e.pos.col));
+    problems
+}
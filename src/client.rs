@@ -1,46 +1,105 @@
 use std::io;
+use std::fs;
+use std::fmt;
+use std::path::Path;
 use io::Read;
 
-use crate::vm::{ ProgramExecutor, Request };
+use texted_adventure::{ ProgramExecutor, Request, Snapshot, VmError, save_snapshot };
+
+use log::debug;
+
+/// Why the client has stopped the story
+pub enum ClientError {
+    Vm(VmError),
+    /// The story ran the amount of instructions without asking the
+    /// player anything. Carries the amount, the address and the location
+    /// of the instruction it stopped at
+    StepLimit(usize, usize, String),
+}
+
+impl From<VmError> for ClientError {
+    fn from(e : VmError) -> ClientError { ClientError::Vm(e) }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Vm(e) => write!(f, "{}", e),
+            ClientError::StepLimit(steps, ip, location) =>
+                write!(f, "the story ran {} instructions without asking the player anything. It was stopped at {} ({})", steps, ip, location),
+        }
+    }
+}
+
+fn write_save(path : &Path, snapshot : &Snapshot) -> Result<(), String> {
+    fs::write(path, save_snapshot(snapshot)).map_err(|e| e.to_string())
+}
 
 /// Stdio client is a simple implementation of the engine's
-/// which is capable of running in the console.
-pub fn stdio_client(exec : ProgramExecutor) {
+/// which is capable of running in the console. If the save
+/// path is given, the player can save the game by typing
+/// `save` when they're asked to pick an option. If the step
+/// limit is given, the story is stopped once it runs that many
+/// instructions without asking the player anything
+pub fn stdio_client(exec : ProgramExecutor, save_path : Option<&Path>, step_limit : Option<usize>) -> Result<(), ClientError> {
     let mut exec = exec;
-    let mut request = exec.unpause(None);
+    // The VM may be resumed from a save, so we ask what it's waiting for
+    let mut request = exec.pending_request();
+    // With the step limit the VM runs one instruction at a time,
+    // so every call to the VM is one step
+    let limit = step_limit.map(|_| 1);
+    let mut steps = 0;
 
     // Welp, it's pretty much an event loop!
     loop {
+        // the story wants to go on without the player
+        if let (Some(x), Request::Resume | Request::PrintMessage(_)) = (step_limit, &request) {
+            if steps >= x { return Err(ClientError::StepLimit(steps, exec.instruction_ptr(), exec.location())) }
+        }
         match request {
-            Request::Drop => break,
-            Request::Resume => { request = exec.unpause(None); },
+            Request::Drop => {
+                debug!(target: "stdio_client", "The story has ended. Variables: {:?}", exec.variables());
+                break
+            },
+            Request::Resume => { request = exec.unpause(limit)?; },
             Request::PrintMessage(msg) => {
                 println!("{}", msg);
-                request = exec.unpause(None);
+                request = exec.unpause(limit)?;
             },
             Request::Wait => {
                 println!("\n[Ok]");
                 io::stdin().read_exact(&mut [0]).unwrap();
-                request = exec.done_printing(None);
+                steps = 0;
+                request = exec.done_printing(limit)?;
             },
-            Request::PerformChoice(choice_slice) => {
+            Request::PerformChoice(options) => {
                 println!("Pick an option");
-                for (i, x) in choice_slice.iter().enumerate() {
-                    println!("{}.) {}", i, x.option_name);
+                for (i, x) in options.iter().enumerate() {
+                    println!("{}.) {}", i, x);
                 }
                 let mut s = String::new();
-                let id;
+                steps = 0;
                 loop {
                     s.clear();
-                    io::stdin().read_line(&mut s).unwrap();
+                    // The input has ended. Nobody is going to pick anything
+                    if io::stdin().read_line(&mut s).unwrap() == 0 { return Ok(()) }
 
-                    if let Ok(x) = s.trim().parse() {
-                        id = x;
-                        break;
+                    if let (Some(path), "save") = (save_path, s.trim()) {
+                        match write_save(path, &exec.snapshot()) {
+                            Ok(()) => println!("Saved to \"{}\"", path.to_string_lossy()),
+                            Err(e) => println!("Couldn't save: {}", e),
+                        }
+                    } else if let Ok(id) = s.trim().parse() {
+                        match exec.choose(id, limit) {
+                            // The VM is still waiting. Just ask again
+                            Err(VmError::InvalidChoice(_, _)) => println!("There's no such option"),
+                            x => { request = x?; break; },
+                        }
                     }
                 }
-                request = exec.choose(id, None);
             }
         }
+        steps += 1;
     }
+    Ok(())
 }
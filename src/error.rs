@@ -0,0 +1,250 @@
+use std::fmt;
+use std::error::Error;
+use std::path::PathBuf;
+
+use crate::marked_yaml::Pos;
+use crate::expr::ExprError;
+use crate::assembly::AsmError;
+
+use yaml_rust::scanner::ScanError;
+
+/// The kinds of errors which can happen while turning
+/// a dialogue file into an executable
+#[derive(Debug)]
+pub enum CompileErrorKind {
+    /// The file isn't a valid YAML document
+    Yaml(ScanError),
+    /// The file contains no documents at all
+    EmptyFile,
+    /// The file's root isn't a hashmap
+    RootNotHash,
+    /// One of the procedures isn't keyed with a string
+    ProcNameNotString,
+    /// The procedure is defined twice
+    DuplicateProc(String),
+    /// The code of the procedure isn't an array
+    ProcNotArray(String),
+    /// The command doesn't match any known command format
+    BadCommand,
+    /// The choice branch isn't a hash with one option and the optional
+    /// `when`, `once` and `fallback` keys
+    BadChoiceBranch,
+    /// The choice branch isn't keyed with a string or its code isn't an array
+    BadChoiceOption,
+    /// The choice has more than one fallback option
+    ManyFallbacks,
+    /// The value isn't an integer, a boolean or an expression
+    BadValue,
+    /// The expression is malformed
+    BadExpr(ExprError),
+    /// The variable's name isn't a string
+    BadVariableName,
+    /// The `if` doesn't consist of `if`, `then` and an optional `else`
+    BadIf,
+    /// An expression or the text of a `print` reads a variable which no
+    /// `set` in the story writes and which isn't listed under `extern`
+    UndeclaredVariable(String),
+    /// A `call` refers to a dialogue which doesn't exist
+    UnknownDialogue(String),
+    /// The `import` isn't a file name or an array of them
+    BadImport,
+    /// The `pub` isn't a dialogue name or an array of them
+    BadPub,
+    /// The `extern` isn't a variable name or an array of them
+    BadExtern,
+    /// The dialogue's name contains `::`, which separates the module name
+    QualifiedProcName(String),
+    /// A `call` refers to a dialogue of another module which isn't `pub`
+    PrivateDialogue(String),
+    /// Two files have the same name, so their modules would be the same
+    DuplicateModule(String),
+    /// The file can't be read. Holds the path and the reason
+    CantRead(String, String),
+    /// The `choose` has no options
+    EmptyChoice,
+    /// Two options of one `choose` have the same name
+    DuplicateOption(String),
+    /// The dialogue can't be reached from `main`
+    UnreachableDialogue(String),
+    /// The dialogues call each other forever without asking the player
+    /// anything. Holds the chain of the calls
+    EndlessRecursion(Vec<String>),
+}
+
+impl fmt::Display for CompileErrorKind {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileErrorKind::Yaml(e) => write!(f, "malformed YAML: {}", e),
+            CompileErrorKind::EmptyFile => write!(f, "the file is empty"),
+            CompileErrorKind::RootNotHash => write!(f, "the file's root must be a hashmap"),
+            CompileErrorKind::ProcNameNotString => write!(f, "the dialogue file must be keyed with strings"),
+            CompileErrorKind::DuplicateProc(name) => write!(f, "the dialogue \"{}\" is defined more than once", name),
+            CompileErrorKind::ProcNotArray(name) => write!(f, "the code of \"{}\" is not an array", name),
+            CompileErrorKind::BadCommand => write!(f, "the command doesn't satisfy any possible format"),
+            CompileErrorKind::BadChoiceBranch => write!(f, "the choice branch must be a hash with one option and optional `when`, `once: bool` and `fallback: bool` keys"),
+            CompileErrorKind::BadChoiceOption => write!(f, "in the choice map the key must be a string and the value must be an array"),
+            CompileErrorKind::ManyFallbacks => write!(f, "the choice can't have more than one fallback option"),
+            CompileErrorKind::BadValue => write!(f, "the value must be an integer, a boolean or an expression"),
+            CompileErrorKind::BadExpr(e) => write!(f, "malformed expression: {}", e),
+            CompileErrorKind::BadVariableName => write!(f, "the variable's name must be a string"),
+            CompileErrorKind::BadIf => write!(f, "the `if` must have a condition, a `then` array and an optional `else` array"),
+            CompileErrorKind::UndeclaredVariable(name) => write!(f, "the variable \"{}\" is never set. The variables set by the game are listed under `extern`, and the texts are quoted, e.g. '\"{}\"'", name, name),
+            CompileErrorKind::UnknownDialogue(name) => write!(f, "unknown dialogue \"{}\"", name),
+            CompileErrorKind::BadImport => write!(f, "the `import` must be a file name or an array of file names"),
+            CompileErrorKind::BadPub => write!(f, "the `pub` must be a dialogue name or an array of dialogue names"),
+            CompileErrorKind::BadExtern => write!(f, "the `extern` must be a variable name or an array of variable names"),
+            CompileErrorKind::QualifiedProcName(name) => write!(f, "the dialogue name \"{}\" can't contain `::`", name),
+            CompileErrorKind::PrivateDialogue(name) => write!(f, "the dialogue \"{}\" isn't `pub`", name),
+            CompileErrorKind::DuplicateModule(name) => write!(f, "the module \"{}\" is made by more than one file", name),
+            CompileErrorKind::CantRead(path, reason) => write!(f, "can't read \"{}\": {}", path, reason),
+            CompileErrorKind::EmptyChoice => write!(f, "the choice has no options"),
+            CompileErrorKind::DuplicateOption(name) => write!(f, "the option \"{}\" is offered more than once", name),
+            CompileErrorKind::UnreachableDialogue(name) => write!(f, "the dialogue \"{}\" can't be reached from \"main\"", name),
+            CompileErrorKind::EndlessRecursion(chain) => write!(f, "the dialogues call each other forever without a choice: {}", chain.join(" -> ")),
+        }
+    }
+}
+
+/// An error which can happen while turning a dialogue
+/// file into an executable, together with the place in
+/// the source which caused it
+#[derive(Debug)]
+pub struct CompileError {
+    pub kind : CompileErrorKind,
+    pub pos : Pos,
+}
+
+impl CompileError {
+    /// The constructor
+    pub fn new(kind : CompileErrorKind, pos : Pos) -> CompileError {
+        CompileError { kind, pos }
+    }
+
+    /// Renders the error the way rustc does: the location, the
+    /// offending line of the source and a caret under the column.
+    pub fn render(&self, file_name : &str, source : &str) -> String {
+        let Pos { line, col } = self.pos;
+        let line_str = line.to_string();
+        let gutter = " ".repeat(line_str.len());
+        let mut out = format!("error: {}\n{}--> {}:{}:{}\n", self.kind, gutter, file_name, line, col);
+        if let Some(text) = source.lines().nth(line.wrapping_sub(1)) {
+            // Keep the tabs, so the caret lines up with the text
+            let padding : String = 
+                text.chars().take(col.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect()
+            ;
+            out.push_str(&format!("{} |\n{} | {}\n{} | {}^\n", gutter, line_str, text, gutter, padding));
+        }
+        out
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.pos.line, self.pos.col, self.kind)
+    }
+}
+
+impl Error for CompileError {}
+
+impl From<ScanError> for CompileError {
+    fn from(e : ScanError) -> Self { 
+        let pos = Pos::from(*e.marker());
+        CompileError::new(CompileErrorKind::Yaml(e), pos)
+    }
+}
+
+/// A compile error together with the file it was found in,
+/// for the stories made of several files
+#[derive(Debug)]
+pub struct SourceError {
+    pub path : PathBuf,
+    pub source : String,
+    pub error : CompileError,
+}
+
+impl SourceError {
+    /// Renders the error against the source of its file.
+    /// See `CompileError::render`
+    pub fn render(&self) -> String {
+        self.error.render(&self.path.to_string_lossy(), &self.source)
+    }
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.path.to_string_lossy(), self.error)
+    }
+}
+
+impl Error for SourceError {}
+
+/// An error which can happen while loading an assembly file,
+/// a bytecode file or a save
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file isn't a valid YAML document
+    Yaml(ScanError),
+    /// The entry table or the opcode array is missing
+    MissingBlocks,
+    /// The opcode block isn't an array
+    OpcodesNotArray,
+    /// The opcode at the given index doesn't match any known format
+    BadOpcode(usize),
+    /// The entry table isn't a hashmap
+    EntryTableNotHash,
+    /// The entry table contains a malformed entry
+    BadEntry,
+    /// The save file is malformed
+    BadSnapshot,
+    /// The object file is malformed
+    BadObject,
+    /// The bytes don't start with the bytecode's magic
+    NotBytecode,
+    /// The bytecode was written by an unknown version of the engine
+    UnsupportedVersion(u16),
+    /// The bytecode ends too early
+    Truncated,
+    /// The bytecode goes on after the executable has ended.
+    /// Carries the offset of the first extra byte
+    TrailingBytes(usize),
+    /// The bytecode refers to a string which doesn't exist or isn't UTF-8
+    BadString(usize),
+    /// The file is neither bytecode nor text
+    NotUtf8,
+    /// The text assembly is malformed
+    Asm(AsmError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Yaml(e) => write!(f, "malformed YAML: {}", e),
+            LoadError::MissingBlocks => write!(f, "not all blocks are present"),
+            LoadError::OpcodesNotArray => write!(f, "the opcode block must be an array"),
+            LoadError::BadOpcode(at) => write!(f, "opcode {} doesn't satisfy any possible format", at),
+            LoadError::EntryTableNotHash => write!(f, "the entry table must be a hashmap"),
+            LoadError::BadEntry => write!(f, "the entry table syntax is not satisfied"),
+            LoadError::BadSnapshot => write!(f, "the save file is malformed"),
+            LoadError::BadObject => write!(f, "the object file is malformed"),
+            LoadError::NotBytecode => write!(f, "the file isn't bytecode"),
+            LoadError::UnsupportedVersion(v) => write!(f, "bytecode version {} isn't supported (expected {})", v, crate::bytecode_saver::VERSION),
+            LoadError::Truncated => write!(f, "the bytecode ends unexpectedly"),
+            LoadError::TrailingBytes(x) => write!(f, "the bytecode has extra bytes after the executable, starting at {}", x),
+            LoadError::BadString(id) => write!(f, "string {} is missing or isn't valid UTF-8", id),
+            LoadError::NotUtf8 => write!(f, "the file is neither bytecode nor assembly"),
+            LoadError::Asm(e) => write!(f, "malformed assembly: {}", e),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<ScanError> for LoadError {
+    fn from(e : ScanError) -> Self { LoadError::Yaml(e) }
+}
+
+impl From<AsmError> for LoadError {
+    fn from(e : AsmError) -> Self { LoadError::Asm(e) }
+}
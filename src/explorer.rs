@@ -0,0 +1,178 @@
+// Plays the story along every possible path. At every choice the VM is
+// snapshotted and each option is tried from the snapshot. The states which
+// were already explored aren't explored again, so the loops of the story
+// end. The state is the instruction pointer and the frame stack, together
+// with the variables and the used up options, since they change what the
+// story does next.
+
+use crate::vm::{ Program, Request, Snapshot, Value, VmError };
+use crate::optimizer::successors;
+
+use std::collections::{ BTreeMap, HashSet };
+use std::fmt;
+use std::sync::Arc;
+
+/// How far the explorer goes
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// The paths with more choices are cut off
+    pub max_choices : usize,
+    /// The amount of instructions the VM may execute without
+    /// asking the player anything before the path is given up
+    pub max_steps : usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits { max_choices : 64, max_steps : 100_000 }
+    }
+}
+
+/// Why a path didn't reach an ending
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// The VM reported an error
+    Vm(VmError),
+    /// The story ran for too long without a choice, most likely forever
+    TooManySteps(usize),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::Vm(e) => write!(f, "{}", e),
+            Failure::TooManySteps(x) => write!(f, "no choice after {} instructions", x),
+        }
+    }
+}
+
+/// What the explorer found out
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// The amount of the paths which reached an ending or failed
+    pub paths : usize,
+    /// The endings keyed with the last message printed before the
+    /// story ended, together with the amount of paths leading there
+    pub endings : BTreeMap<String, usize>,
+    /// The addresses of the instructions which were never executed. The
+    /// ones which can't be run at all, like the `ret`s left after the tail
+    /// calls, are the compiler's leftovers, so they aren't counted
+    pub unreachable : Vec<usize>,
+    /// The most choices made on one path
+    pub max_depth : usize,
+    /// The options picked on the way and what went wrong there.
+    /// Only the first path is kept for each failure
+    pub failures : Vec<(Vec<String>, Failure)>,
+    /// The paths which came back to a state which was already explored
+    pub loops : usize,
+    /// The paths which were cut off by `Limits::max_choices`
+    pub cut_off : usize,
+}
+
+// A path to explore: the snapshot to start from, the option to pick
+// there, the options picked so far and the last message printed
+struct Path {
+    snapshot : Snapshot,
+    option : Option<usize>,
+    picked : Vec<String>,
+    last_message : Option<String>,
+}
+
+// How the explored piece of a path has stopped
+enum Stop {
+    Ending,
+    Choice,
+    Failure(Failure),
+}
+
+/// Explores every path through the program, starting with the given variables
+pub fn explore(program : &Arc<Program>, variables : &[(String, Value)], limits : Limits) -> Report {
+    let mut report = Report::default();
+    let mut executed = vec![false; program.opcodes().len()];
+    let mut seen = HashSet::new();
+
+    let mut exec = program.run();
+    variables.iter().for_each(|(name, value)| exec.set_variable(name, value.clone()));
+    let mut stack = vec![Path { snapshot : exec.snapshot(), option : None, picked : Vec::new(), last_message : None }];
+
+    while let Some(Path { snapshot, option, picked, mut last_message }) = stack.pop() {
+        report.max_depth = report.max_depth.max(picked.len());
+        // The snapshots are taken from the same program, so they always fit
+        let mut exec = program.resume(snapshot).unwrap();
+        let mut request = {
+            match option {
+                // A zero limit only jumps to the option
+                Some(x) => exec.choose(x, Some(0)),
+                None => Ok(exec.pending_request()),
+            }
+        };
+
+        let mut steps = 0;
+        let stop = loop {
+            // The instruction is either executed next or was just executed
+            if let Some(x) = executed.get_mut(exec.instruction_ptr()) { *x = true; }
+            if steps > limits.max_steps { break Stop::Failure(Failure::TooManySteps(limits.max_steps)) }
+            steps += 1;
+            request = {
+                match request {
+                    Ok(Request::Drop) => {
+                        let ending = last_message.take().unwrap_or_default();
+                        *report.endings.entry(ending).or_insert(0) += 1;
+                        break Stop::Ending
+                    },
+                    Ok(Request::PrintMessage(msg)) => {
+                        last_message = Some(msg);
+                        exec.unpause(Some(1))
+                    },
+                    Ok(Request::Resume) => exec.unpause(Some(1)),
+                    Ok(Request::Wait) => exec.done_printing(Some(1)),
+                    Ok(Request::PerformChoice(options)) => {
+                        if picked.len() >= limits.max_choices { report.cut_off += 1; }
+                        else if !seen.insert(exec.snapshot()) { report.loops += 1; }
+                        else {
+                            // Reversed, so the first option is explored first
+                            for (i, name) in options.into_iter().enumerate().rev() {
+                                let mut picked = picked.clone();
+                                picked.push(name);
+                                stack.push(Path { snapshot : exec.snapshot(), option : Some(i), picked, last_message : last_message.clone() });
+                            }
+                        }
+                        break Stop::Choice
+                    },
+                    Err(e) => break Stop::Failure(Failure::Vm(e)),
+                }
+            };
+        };
+
+        match stop {
+            // The path goes on in its options
+            Stop::Choice => (),
+            Stop::Ending => report.paths += 1,
+            Stop::Failure(failure) => {
+                report.paths += 1;
+                if report.failures.iter().all(|(_, x)| *x != failure) {
+                    report.failures.push((picked, failure));
+                }
+            },
+        }
+    }
+
+    // The instructions the VM can get to from where it may start
+    let mut possible = vec![false; executed.len()];
+    let mut stack = program.starts().collect::<Vec<_>>();
+    while let Some(address) = stack.pop() {
+        match possible.get_mut(address) {
+            Some(x) if !*x => *x = true,
+            _ => continue,
+        }
+        stack.extend(successors(program.opcodes(), address));
+    }
+
+    report.unreachable =
+        executed.iter().zip(possible.iter()).enumerate()
+        .filter(|(_, (executed, possible))| !**executed && **possible)
+        .map(|(i, _)| i)
+        .collect()
+    ;
+    report
+}
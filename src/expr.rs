@@ -0,0 +1,465 @@
+use crate::vm::{ BinOp, UnOp, Value };
+
+use std::fmt;
+
+/// An expression used in `set` and `if`. It's compiled into
+/// the instructions for the VM's value stack.
+#[derive(Debug, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    Var(String),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    // `&&` and `||` don't evaluate the right side when they don't
+    // need to, so they aren't ordinary binary operators
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// A piece of a `print` text. The expressions are written in braces
+/// and remember the offset where they start
+#[derive(Debug, PartialEq)]
+pub enum TemplatePart {
+    Text(String),
+    Expr(Expr, usize),
+}
+
+impl Expr {
+    /// The names of the variables the expression reads
+    pub fn variables(&self) -> Vec<&str> {
+        match self {
+            Expr::Literal(_) => Vec::new(),
+            Expr::Var(x) => vec![x.as_str()],
+            Expr::Unary(_, x) => x.variables(),
+            Expr::Binary(_, lhs, rhs) | Expr::And(lhs, rhs) | Expr::Or(lhs, rhs) => {
+                let mut res = lhs.variables();
+                res.extend(rhs.variables());
+                res
+            },
+        }
+    }
+}
+
+/// A malformed expression. The offset is counted in characters
+/// from the start of the expression
+#[derive(Debug, PartialEq, Eq)]
+pub struct ExprError {
+    pub message : String,
+    pub offset : usize,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(i64),
+    Str(String),
+    Ident(String),
+    True,
+    False,
+    LParen,
+    RParen,
+    Not,
+    And,
+    Or,
+    Bin(BinOp),
+    // `-` is both a unary and a binary operator
+    Minus,
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Int(x) => write!(f, "`{}`", x),
+            Token::Str(x) => write!(f, "`{:?}`", x),
+            Token::Ident(x) => write!(f, "`{}`", x),
+            Token::True => write!(f, "`true`"),
+            Token::False => write!(f, "`false`"),
+            Token::LParen => write!(f, "`(`"),
+            Token::RParen => write!(f, "`)`"),
+            Token::Not => write!(f, "`!`"),
+            Token::And => write!(f, "`&&`"),
+            Token::Or => write!(f, "`||`"),
+            Token::Bin(op) => write!(f, "`{}`", op.symbol()),
+            Token::Minus => write!(f, "`-`"),
+            Token::End => write!(f, "the end of the expression"),
+        }
+    }
+}
+
+fn error<T>(message : String, offset : usize) -> Result<T, ExprError> {
+    Err(ExprError { message, offset })
+}
+
+// Splits the source into tokens, remembering where each of them starts
+fn tokenize(src : &str) -> Result<Vec<(Token, usize)>, ExprError> {
+    let chars : Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let token = {
+            match c {
+                _ if c.is_whitespace() => { i += 1; continue },
+                '0'..='9' => {
+                    while i < chars.len() && chars[i].is_ascii_digit() { i += 1; }
+                    let digits : String = chars[start..i].iter().collect();
+                    match digits.parse() {
+                        Ok(x) => Token::Int(x),
+                        Err(_) => return error("the number is too large".to_string(), start),
+                    }
+                },
+                'a'..='z' | 'A'..='Z' | '_' => {
+                    while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') { i += 1; }
+                    let word : String = chars[start..i].iter().collect();
+                    match word.as_str() {
+                        "true" => Token::True,
+                        "false" => Token::False,
+                        "not" => Token::Not,
+                        "and" => Token::And,
+                        "or" => Token::Or,
+                        _ => Token::Ident(word),
+                    }
+                },
+                '"' | '\'' => {
+                    let mut s = String::new();
+                    i += 1;
+                    loop {
+                        match chars.get(i) {
+                            None => return error("the string is never closed".to_string(), start),
+                            Some(x) if *x == c => { i += 1; break },
+                            Some('\\') => {
+                                match chars.get(i + 1) {
+                                    Some('n') => s.push('\n'),
+                                    Some(x) => s.push(*x),
+                                    None => return error("the string is never closed".to_string(), start),
+                                }
+                                i += 2;
+                            },
+                            Some(x) => { s.push(*x); i += 1; },
+                        }
+                    }
+                    Token::Str(s)
+                },
+                _ => {
+                    let (token, len) = {
+                        match (c, next) {
+                            ('(', _) => (Token::LParen, 1),
+                            (')', _) => (Token::RParen, 1),
+                            ('&', Some('&')) => (Token::And, 2),
+                            ('|', Some('|')) => (Token::Or, 2),
+                            ('=', Some('=')) => (Token::Bin(BinOp::Eq), 2),
+                            ('!', Some('=')) => (Token::Bin(BinOp::Ne), 2),
+                            ('<', Some('=')) => (Token::Bin(BinOp::Le), 2),
+                            ('>', Some('=')) => (Token::Bin(BinOp::Ge), 2),
+                            ('<', _) => (Token::Bin(BinOp::Lt), 1),
+                            ('>', _) => (Token::Bin(BinOp::Gt), 1),
+                            ('!', _) => (Token::Not, 1),
+                            ('+', _) => (Token::Bin(BinOp::Add), 1),
+                            ('-', _) => (Token::Minus, 1),
+                            ('*', _) => (Token::Bin(BinOp::Mul), 1),
+                            ('/', _) => (Token::Bin(BinOp::Div), 1),
+                            ('%', _) => (Token::Bin(BinOp::Rem), 1),
+                            ('=', _) => return error("unexpected `=`. Did you mean `==`?".to_string(), start),
+                            _ => return error(format!("unexpected character `{}`", c), start),
+                        }
+                    };
+                    i += len;
+                    token
+                },
+            }
+        };
+        tokens.push((token, start));
+    }
+    tokens.push((Token::End, chars.len()));
+    Ok(tokens)
+}
+
+// A precedence climbing parser. From the loosest to the tightest:
+//   `||`, `&&`, comparisons, `+ -`, `* / %`, unary `! -`
+struct ExprParser {
+    tokens : Vec<(Token, usize)>,
+    cursor : usize,
+}
+
+impl ExprParser {
+    fn peek(&self) -> &Token { &self.tokens[self.cursor].0 }
+
+    fn offset(&self) -> usize { self.tokens[self.cursor].1 }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.cursor].0.clone();
+        if token != Token::End { self.cursor += 1; }
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ExprError> {
+        let mut lhs = self.parse_and()?;
+        while *self.peek() == Token::Or {
+            self.advance();
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.parse_and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ExprError> {
+        let mut lhs = self.parse_comparison()?;
+        while *self.peek() == Token::And {
+            self.advance();
+            lhs = Expr::And(Box::new(lhs), Box::new(self.parse_comparison()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ExprError> {
+        let lhs = self.parse_sum()?;
+        match self.peek().clone() {
+            Token::Bin(op) if op.is_comparison() => {
+                self.advance();
+                let rhs = self.parse_sum()?;
+                // `a < b < c` doesn't mean what people expect
+                if let Token::Bin(op) = self.peek() {
+                    if op.is_comparison() {
+                        return error("comparisons can't be chained. Use `&&`".to_string(), self.offset());
+                    }
+                }
+                Ok(Expr::Binary(op, Box::new(lhs), Box::new(rhs)))
+            },
+            _ => Ok(lhs),
+        }
+    }
+
+    fn parse_sum(&mut self) -> Result<Expr, ExprError> {
+        let mut lhs = self.parse_product()?;
+        loop {
+            let op = {
+                match self.peek() {
+                    Token::Bin(BinOp::Add) => BinOp::Add,
+                    Token::Minus => BinOp::Sub,
+                    _ => return Ok(lhs),
+                }
+            };
+            self.advance();
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.parse_product()?));
+        }
+    }
+
+    fn parse_product(&mut self) -> Result<Expr, ExprError> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let op = {
+                match self.peek() {
+                    Token::Bin(op @ (BinOp::Mul | BinOp::Div | BinOp::Rem)) => *op,
+                    _ => return Ok(lhs),
+                }
+            };
+            self.advance();
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.parse_unary()?));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ExprError> {
+        match self.peek() {
+            Token::Not => { self.advance(); Ok(Expr::Unary(UnOp::Not, Box::new(self.parse_unary()?))) },
+            Token::Minus => { self.advance(); Ok(Expr::Unary(UnOp::Neg, Box::new(self.parse_unary()?))) },
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ExprError> {
+        let offset = self.offset();
+        match self.advance() {
+            Token::Int(x) => Ok(Expr::Literal(Value::Int(x))),
+            Token::Str(x) => Ok(Expr::Literal(Value::Str(x))),
+            Token::True => Ok(Expr::Literal(Value::Bool(true))),
+            Token::False => Ok(Expr::Literal(Value::Bool(false))),
+            Token::Ident(x) => Ok(Expr::Var(x)),
+            Token::LParen => {
+                let inner = self.parse_or()?;
+                match self.peek() {
+                    Token::RParen => { self.advance(); Ok(inner) },
+                    x => error(format!("expected `)`, found {}", x), self.offset()),
+                }
+            },
+            x => error(format!("expected a value, found {}", x), offset),
+        }
+    }
+}
+
+/// Parses an expression
+pub fn parse_expr(src : &str) -> Result<Expr, ExprError> {
+    let mut parser = ExprParser { tokens : tokenize(src)?, cursor : 0 };
+    let expr = parser.parse_or()?;
+    match parser.peek() {
+        Token::End => Ok(expr),
+        x => error(format!("unexpected {} after the expression", x), parser.offset()),
+    }
+}
+
+/// Splits a `print` text into the plain text and the expressions
+/// in braces. `{{` and `}}` stand for the braces themselves
+pub fn parse_template(src : &str) -> Result<Vec<TemplatePart>, ExprError> {
+    let chars : Vec<char> = src.chars().collect();
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match (chars[i], chars.get(i + 1)) {
+            ('{', Some('{')) => { text.push('{'); i += 2; },
+            ('}', Some('}')) => { text.push('}'); i += 2; },
+            ('}', _) => return error("unexpected `}`. Use `}}` to print it".to_string(), i),
+            ('{', _) => {
+                // The expression ends at the first `}` which isn't in a string
+                let start = i + 1;
+                let mut end = start;
+                let mut quote = None;
+                loop {
+                    match (chars.get(end), quote) {
+                        (None, _) => return error("the `{` is never closed".to_string(), i),
+                        (Some('}'), None) => break,
+                        (Some('"'), None) | (Some('\''), None) => { quote = Some(chars[end]); },
+                        (Some('\\'), Some(_)) => { end += 1; },
+                        (Some(c), Some(q)) if *c == q => { quote = None; },
+                        _ => (),
+                    }
+                    end += 1;
+                }
+                let inner : String = chars[start..end].iter().collect();
+                let expr = parse_expr(&inner).map_err(|e| ExprError { offset : start + e.offset, ..e })?;
+                if !text.is_empty() { parts.push(TemplatePart::Text(std::mem::take(&mut text))); }
+                parts.push(TemplatePart::Expr(expr, start));
+                i = end + 1;
+            },
+            (c, _) => { text.push(c); i += 1; },
+        }
+    }
+    if !text.is_empty() { parts.push(TemplatePart::Text(text)); }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(x : i64) -> Box<Expr> { Box::new(Expr::Literal(Value::Int(x))) }
+
+    fn var(x : &str) -> Box<Expr> { Box::new(Expr::Var(x.to_string())) }
+
+    fn offset_of(src : &str) -> usize { parse_expr(src).unwrap_err().offset }
+
+    #[test]
+    fn products_bind_tighter_than_sums() {
+        assert_eq!(
+            parse_expr("1 + 2 * 3 - 4").unwrap(),
+            Expr::Binary(
+                BinOp::Sub,
+                Box::new(Expr::Binary(BinOp::Add, int(1), Box::new(Expr::Binary(BinOp::Mul, int(2), int(3))))),
+                int(4),
+            )
+        );
+        assert_eq!(
+            parse_expr("(1 + 2) * 3").unwrap(),
+            Expr::Binary(BinOp::Mul, Box::new(Expr::Binary(BinOp::Add, int(1), int(2))), int(3))
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse_expr("a || b && c < 1").unwrap(),
+            Expr::Or(var("a"), Box::new(Expr::And(var("b"), Box::new(Expr::Binary(BinOp::Lt, var("c"), int(1)))))),
+        );
+        assert_eq!(parse_expr("a or b and c").unwrap(), parse_expr("a || b && c").unwrap());
+    }
+
+    #[test]
+    fn unary_operators_nest() {
+        assert_eq!(
+            parse_expr("!-x == -1").unwrap(),
+            Expr::Binary(
+                BinOp::Eq,
+                Box::new(Expr::Unary(UnOp::Not, Box::new(Expr::Unary(UnOp::Neg, var("x"))))),
+                Box::new(Expr::Unary(UnOp::Neg, int(1))),
+            )
+        );
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(parse_expr("'it\\'s'").unwrap(), Expr::Literal(Value::Str("it's".to_string())));
+        assert_eq!(parse_expr("\"a\\nb\"").unwrap(), Expr::Literal(Value::Str("a\nb".to_string())));
+        assert_eq!(parse_expr("true").unwrap(), Expr::Literal(Value::Bool(true)));
+        assert_eq!(parse_expr("not false").unwrap(), Expr::Unary(UnOp::Not, Box::new(Expr::Literal(Value::Bool(false)))));
+    }
+
+    #[test]
+    fn comparisons_cant_be_chained() {
+        let e = parse_expr("1 < x < 3").unwrap_err();
+        assert_eq!(e.offset, 6);
+        assert!(e.message.contains("chained"));
+        // the parentheses make it explicit
+        assert!(parse_expr("(1 < x) == true").is_ok());
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(offset_of("1 + "), 4);
+        assert_eq!(offset_of("x = 1"), 2);
+        assert_eq!(offset_of("1 # 2"), 2);
+        assert_eq!(offset_of("(1 + 2"), 6);
+        assert_eq!(offset_of("1 2"), 2);
+        assert_eq!(offset_of("a + 'never closed"), 4);
+        assert_eq!(offset_of("99999999999999999999"), 0);
+        // the offset is counted in characters, not bytes
+        assert_eq!(offset_of("'ё' + )"), 6);
+    }
+
+    #[test]
+    fn variables_are_listed_in_order() {
+        assert_eq!(parse_expr("a + b * a || !c").unwrap().variables(), vec!["a", "b", "a", "c"]);
+    }
+
+    #[test]
+    fn templates_split_into_text_and_expressions() {
+        assert_eq!(
+            parse_template("Hi {name}, {{x}} is {1 + 2}").unwrap(),
+            vec![
+                TemplatePart::Text("Hi ".to_string()),
+                TemplatePart::Expr(*var("name"), 4),
+                TemplatePart::Text(", {x} is ".to_string()),
+                TemplatePart::Expr(Expr::Binary(BinOp::Add, int(1), int(2)), 21),
+            ]
+        );
+        // a brace inside of a string doesn't end the expression
+        assert_eq!(parse_template("{'}'}").unwrap(), vec![TemplatePart::Expr(Expr::Literal(Value::Str("}".to_string())), 1)]);
+    }
+
+    #[test]
+    fn template_errors_are_offset_from_the_text_start() {
+        assert_eq!(parse_template("ab {1 +}").unwrap_err().offset, 7);
+        assert_eq!(parse_template("ab {x").unwrap_err().offset, 3);
+        assert_eq!(parse_template("a } b").unwrap_err().offset, 2);
+    }
+
+    #[test]
+    fn and_and_or_skip_the_right_side() {
+        // the right sides would divide by zero if they ran
+        let exe = crate::compile_source(
+            r#"
+main:
+  - set: { a: "false && 1 / 0 == 0", b: "true || 1 / 0 == 0", c: "true && 2 > 1", d: "false || 1 > 2" }
+"#
+        ).unwrap();
+        let mut exec = std::sync::Arc::new(exe.into_program("main").unwrap()).run();
+        assert!(matches!(exec.unpause(None), Ok(crate::Request::Drop)));
+        let values = ["a", "b", "c", "d"].iter().map(|x| exec.variables()[*x].clone()).collect::<Vec<_>>();
+        assert_eq!(values, vec![Value::Bool(false), Value::Bool(true), Value::Bool(true), Value::Bool(false)]);
+    }
+}
@@ -0,0 +1,186 @@
+// The story drawn as a graph: the dialogues and the choices are the nodes,
+// and the calls are the edges. The edges which start in a choice are
+// labelled with the option. An option which calls nothing leads to the
+// "continue" node of its choice, since the dialogue just goes on after
+// the choice, and the calls after the choice start there. The calls which
+// may not happen, the ones inside of `if`s and the options with `when`,
+// are drawn dashed
+
+use crate::parser::{ Ast, AstKind, File };
+use crate::translator::{ call_target, qualified_name };
+
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    Dialogue,
+    Choice,
+    /// The place where the dialogue goes on after the choice
+    Continue,
+}
+
+#[derive(Debug)]
+pub struct Node {
+    pub kind : NodeKind,
+    pub label : String,
+}
+
+#[derive(Debug)]
+pub struct Edge {
+    pub from : usize,
+    pub to : usize,
+    /// The option which leads along the edge
+    pub label : Option<String>,
+    pub conditional : bool,
+}
+
+/// The graph of the dialogues, the choices and the calls
+#[derive(Debug)]
+pub struct Graph {
+    pub nodes : Vec<Node>,
+    pub edges : Vec<Edge>,
+}
+
+// Escapes the text for a quoted DOT string
+fn dot_escape(s : &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// Mermaid has no escapes inside of the quotes, only the entity codes
+fn mermaid_escape(s : &str) -> String {
+    s.replace('"', "#quot;").replace('\n', " ")
+}
+
+struct Builder {
+    graph : Graph,
+    dialogues : HashMap<String, usize>,
+}
+
+impl Builder {
+    fn node(&mut self, kind : NodeKind, label : &str) -> usize {
+        self.graph.nodes.push(Node { kind, label : label.to_string() });
+        self.graph.nodes.len() - 1
+    }
+
+    // The dialogues which don't exist still get a node
+    fn dialogue(&mut self, name : String) -> usize {
+        if let Some(x) = self.dialogues.get(&name) { return *x }
+        let id = self.node(NodeKind::Dialogue, &name);
+        self.dialogues.insert(name, id);
+        id
+    }
+
+    fn edge(&mut self, from : usize, to : usize, label : Option<&str>, conditional : bool) {
+        self.graph.edges.push(Edge { from, to, label : label.map(str::to_string), conditional });
+    }
+
+    fn walk(&mut self, code : &[Ast], module : &str, mut from : usize, mut label : Option<&str>, conditional : bool) {
+        for ast in code.iter() {
+            match &ast.kind {
+                AstKind::Call(x) => {
+                    let to = self.dialogue(call_target(module, x));
+                    self.edge(from, to, label, conditional);
+                },
+                AstKind::Choice(options) => {
+                    let choice = self.node(NodeKind::Choice, "choose");
+                    self.edge(from, choice, label, conditional);
+                    let mut after = None;
+                    for option in options.iter() {
+                        let conditional = conditional || option.when.is_some();
+                        let edge_count = self.graph.edges.len();
+                        self.walk(&option.code, module, choice, Some(&option.name), conditional);
+                        if self.graph.edges.len() == edge_count {
+                            let to = *after.get_or_insert_with(|| self.node(NodeKind::Continue, "continue"));
+                            self.edge(choice, to, Some(&option.name), conditional);
+                        }
+                    }
+                    // the option is already on the way there
+                    if let Some(after) = after {
+                        from = after;
+                        label = None;
+                    }
+                },
+                AstKind::If(_, then_code, else_code) => {
+                    self.walk(then_code, module, from, label, true);
+                    self.walk(else_code, module, from, label, true);
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Draws the files of the story, which make the modules with the given names
+pub fn story_graph(files : &[File], modules : &[String]) -> Graph {
+    let mut builder = Builder { graph : Graph { nodes : Vec::new(), edges : Vec::new() }, dialogues : HashMap::new() };
+    // The defined dialogues go first, in the order they were written
+    for (file, module) in files.iter().zip(modules.iter()) {
+        for name in file.procs.keys() {
+            builder.dialogue(qualified_name(module, name));
+        }
+    }
+    for (file, module) in files.iter().zip(modules.iter()) {
+        for (name, proc) in file.procs.iter() {
+            let from = builder.dialogue(qualified_name(module, name));
+            builder.walk(&proc.code, module, from, None, false);
+        }
+    }
+    builder.graph
+}
+
+impl Graph {
+    /// Writes the graph in the Graphviz DOT language
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph story {\n");
+        for (id, node) in self.nodes.iter().enumerate() {
+            let shape = {
+                match node.kind {
+                    NodeKind::Dialogue => "box",
+                    NodeKind::Choice => "diamond",
+                    NodeKind::Continue => "circle",
+                }
+            };
+            out.push_str(&format!("    n{} [label=\"{}\", shape={}];\n", id, dot_escape(&node.label), shape));
+        }
+        for edge in self.edges.iter() {
+            let mut attributes = Vec::new();
+            if let Some(label) = &edge.label { attributes.push(format!("label=\"{}\"", dot_escape(label))) }
+            if edge.conditional { attributes.push("style=dashed".to_string()) }
+            let attributes = {
+                if attributes.is_empty() { String::new() }
+                else { format!(" [{}]", attributes.join(", ")) }
+            };
+            out.push_str(&format!("    n{} -> n{}{};\n", edge.from, edge.to, attributes));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Writes the graph as a Mermaid flowchart
+    pub fn to_mermaid(&self) -> String {
+        let mut out = String::from("flowchart TD\n");
+        for (id, node) in self.nodes.iter().enumerate() {
+            let label = mermaid_escape(&node.label);
+            let node = {
+                match node.kind {
+                    NodeKind::Dialogue => format!("[\"{}\"]", label),
+                    NodeKind::Choice => format!("{{\"{}\"}}", label),
+                    NodeKind::Continue => format!("((\"{}\"))", label),
+                }
+            };
+            out.push_str(&format!("    n{}{}\n", id, node));
+        }
+        for edge in self.edges.iter() {
+            let arrow = {
+                match (&edge.label, edge.conditional) {
+                    (Some(label), false) => format!("-->|\"{}\"|", mermaid_escape(label)),
+                    (Some(label), true) => format!("-.->|\"{}\"|", mermaid_escape(label)),
+                    (None, false) => "-->".to_string(),
+                    (None, true) => "-.->".to_string(),
+                }
+            };
+            out.push_str(&format!("    n{} {} n{}\n", edge.from, arrow, edge.to));
+        }
+        out
+    }
+}
@@ -0,0 +1,327 @@
+//! The engine for the text based adventures. A dialogue file is compiled
+//! into an `Executable`, which is then run by the VM:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! let exe = texted_adventure::compile_source("main: [{print: hi}]").unwrap();
+//! let program = Arc::new(exe.into_program("main").unwrap());
+//! let mut exec = program.run();
+//! let request = exec.unpause(None).unwrap();
+//! ```
+//!
+//! The program is shared through an `Arc`, so many play sessions, even
+//! on different threads, can run one compiled story. The executables
+//! can be stored as text assembly (`disassemble` and `assemble`), as
+//! YAML assembly (`save_assembly` and `load_assembly`) or as compact
+//! bytecode, and `load_executable` reads any of them. The snapshots of
+//! the VM are stored as YAML.
+
+// Only the facade below is the API. The modules stay private, so
+// their internals can change without breaking anybody
+mod vm;
+mod linker;
+mod parser;
+mod expr;
+mod translator;
+mod error;
+mod marked_yaml;
+mod assembly;
+mod checker;
+mod graph;
+mod explorer;
+mod optimizer;
+mod opcode_saver;
+mod opcode_loader;
+mod bytecode_saver;
+mod bytecode_loader;
+mod save_file;
+mod object_file;
+
+pub use linker::Executable;
+pub use translator::ObjectFiles;
+pub use vm::{ BinOp, BranchLeaf, DEFAULT_MAX_DEPTH, Instruction, Program, ProgramExecutor, ProgramState, Request, Snapshot, TemplatePiece, UnOp, Value, VmError };
+pub use error::{ CompileError, CompileErrorKind, LoadError, SourceError };
+pub use marked_yaml::Pos;
+pub use expr::ExprError;
+pub use assembly::{ AsmError, assemble, disassemble };
+pub use graph::{ Edge, Graph, Node, NodeKind };
+pub use explorer::{ Failure, Limits, Report, explore };
+pub use optimizer::optimize;
+
+use parser::{ File, parse_yaml };
+use translator::{ module_name, translate_file };
+use linker::{ link, link_reachable };
+
+use std::collections::{ HashSet, VecDeque };
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+
+use yaml_rust::emitter::YamlEmitter;
+use yaml_rust::yaml::{ Yaml, YamlLoader };
+
+fn parse_source(source : &str) -> Result<File, CompileError> {
+    let mut res = marked_yaml::load_from_str(source)?;
+    let yaml = res.pop().ok_or(CompileError::new(CompileErrorKind::EmptyFile, Pos { line : 1, col : 1 }))?;
+    parse_yaml(yaml)
+}
+
+fn cant_read(path : &Path, e : io::Error) -> CompileErrorKind {
+    CompileErrorKind::CantRead(path.to_string_lossy().into_owned(), e.to_string())
+}
+
+/// Compiles the source of a dialogue file. The source can't
+/// `import` anything, since there's no file to look next to.
+/// See `compile_files` for that
+pub fn compile_source(source : &str) -> Result<Executable, CompileError> {
+    let file = parse_source(source)?;
+    if let Some((path, pos)) = file.imports.first() {
+        let kind = CompileErrorKind::CantRead(path.clone(), "only the files can import other files".to_string());
+        return Err(CompileError::new(kind, *pos))
+    }
+    // The source isn't a file, so it isn't a module either
+    link(vec![translate_file(file, Path::new(""))?]).map_err(|(_, e)| e)
+}
+
+// The paths of the files with what's written in them
+type Sources = Vec<(PathBuf, String)>;
+
+// Reads and parses the files together with the files they `import`. The
+// imports are looked up next to the file which imports them, and every
+// file is read once. The files which can't be read or parsed are skipped
+// and their errors are collected
+// Also returns the modules of the files which failed to load
+fn load_files<P : AsRef<Path>>(paths : &[P], errors : &mut Vec<SourceError>) -> (Vec<File>, Sources, Vec<String>) {
+    // every path comes with the file and the place which imported it
+    let mut queue : VecDeque<(PathBuf, Option<(usize, Pos)>)> = 
+        paths.iter()
+        .map(|x| (x.as_ref().to_path_buf(), None))
+        .collect()
+    ;
+    let mut seen = HashSet::new();
+    let mut sources : Vec<(PathBuf, String)> = Vec::new();
+    let mut files = Vec::new();
+    let mut broken = Vec::new();
+
+    while let Some((path, importer)) = queue.pop_front() {
+        let read = fs::canonicalize(&path).and_then(|full| Ok((full, fs::read_to_string(&path)?)));
+        let (full, source) = {
+            match read {
+                Ok(x) => x,
+                Err(e) => {
+                    broken.push(module_name(&path));
+                    let kind = cant_read(&path, e);
+                    errors.push(
+                        match importer {
+                            Some((id, pos)) => located(&sources, (id, CompileError::new(kind, pos))),
+                            None => SourceError { path, source : String::new(), error : CompileError::new(kind, Pos { line : 1, col : 1 }) },
+                        }
+                    );
+                    continue;
+                },
+            }
+        };
+        if !seen.insert(full) { continue }
+
+        let file = {
+            match parse_source(&source) {
+                Ok(x) => x,
+                Err(error) => {
+                    broken.push(module_name(&path));
+                    errors.push(SourceError { path, source, error });
+                    continue;
+                },
+            }
+        };
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        for (import, pos) in file.imports.iter() {
+            queue.push_back((dir.join(import), Some((sources.len(), *pos))));
+        }
+        files.push(file);
+        sources.push((path, source));
+    }
+    (files, sources, broken)
+}
+
+fn located(sources : &[(PathBuf, String)], (id, error) : (usize, CompileError)) -> SourceError {
+    let (path, source) = sources[id].clone();
+    SourceError { path, source, error }
+}
+
+/// Compiles the dialogue files together with the files they `import`.
+/// The imports are looked up next to the file which imports them.
+/// Each file is parsed on its own and compiled once, no matter how
+/// many times it's imported, and then all of them are linked together.
+/// Every file is a module named after the file, e.g. the dialogue
+/// `intro` of `chapter1.diag` is called `chapter1::intro`
+pub fn compile_files<P : AsRef<Path>>(paths : &[P]) -> Result<Executable, SourceError> {
+    let (objects, sources) = translate_files(paths)?;
+    link(objects).map_err(|e| located(&sources, e))
+}
+
+/// Like `compile_files`, but only the dialogues which can be reached from
+/// the roots are linked. The roots have to be public dialogues, given
+/// by their full names. The dialogues which are left out still have to
+/// link. Returns the full names of the dialogues which were left out too
+pub fn compile_files_reachable<P : AsRef<Path>>(paths : &[P], roots : &[String]) -> Result<(Executable, Vec<String>), SourceError> {
+    let (objects, sources) = translate_files(paths)?;
+    link_reachable(objects, roots).map_err(|e| located(&sources, e))
+}
+
+fn translate_files<P : AsRef<Path>>(paths : &[P]) -> Result<(Vec<ObjectFiles>, Sources), SourceError> {
+    let mut errors = Vec::new();
+    let (files, sources, _) = load_files(paths, &mut errors);
+    if !errors.is_empty() { return Err(errors.remove(0)) }
+
+    let objects = 
+        files.into_iter().enumerate()
+        .map(|(id, file)| translate_file(file, &sources[id].0).map_err(|e| located(&sources, (id, e))))
+        .collect::<Result<_, _>>()?
+    ;
+    Ok((objects, sources))
+}
+
+/// Checks the dialogue files and the files they `import` without running
+/// them, and returns every problem found. Besides the compile errors it
+/// finds the problems which would otherwise only show up while playing:
+/// the choices without options, the options with the same name in one
+/// choice, the dialogues which can't be reached from `main` and the
+/// dialogues which call each other forever
+pub fn check_files<P : AsRef<Path>>(paths : &[P]) -> Vec<SourceError> {
+    let mut errors = Vec::new();
+    let (files, sources, broken) = load_files(paths, &mut errors);
+    let modules = sources.iter().map(|(path, _)| module_name(path)).collect::<Vec<_>>();
+    errors.extend(checker::check(&files, &modules, &broken).into_iter().map(|e| located(&sources, e)));
+    if !errors.is_empty() { return errors }
+
+    // The checker covers what the linker looks at, so this is just to be sure
+    let objects = 
+        files.into_iter().enumerate()
+        .map(|(id, file)| translate_file(file, &sources[id].0).map_err(|e| located(&sources, (id, e))))
+        .collect::<Result<_, _>>()
+    ;
+    if let Err(e) = objects.and_then(|x| link(x).map_err(|e| located(&sources, e))) { errors.push(e) }
+    errors
+}
+
+/// Draws the dialogue files and the files they `import` as a graph of
+/// the dialogues, the choices and the calls. See `Graph`
+pub fn story_graph<P : AsRef<Path>>(paths : &[P]) -> Result<Graph, SourceError> {
+    let mut errors = Vec::new();
+    let (files, sources, _) = load_files(paths, &mut errors);
+    if !errors.is_empty() { return Err(errors.remove(0)) }
+    let modules = sources.iter().map(|(path, _)| module_name(path)).collect::<Vec<_>>();
+    Ok(graph::story_graph(&files, &modules))
+}
+
+/// Compiles one dialogue file into the objects of its module. The files
+/// it imports aren't compiled, so every file can be compiled on its own
+/// and only the changed ones have to be compiled again. The objects are
+/// put together by `link_objects`
+pub fn compile_object<P : AsRef<Path>>(path : P) -> Result<ObjectFiles, SourceError> {
+    let path = path.as_ref().to_path_buf();
+    let source = {
+        match fs::read_to_string(&path) {
+            Ok(x) => x,
+            Err(e) => {
+                let error = CompileError::new(cant_read(&path, e), Pos { line : 1, col : 1 });
+                return Err(SourceError { path, source : String::new(), error })
+            },
+        }
+    };
+    match parse_source(&source).and_then(|x| translate_file(x, &path)) {
+        Ok(x) => Ok(x),
+        Err(error) => Err(SourceError { path, source, error }),
+    }
+}
+
+/// Links the objects of several modules into one executable. The errors
+/// are rendered against the dialogue files the objects were made from
+pub fn link_objects(objects : Vec<ObjectFiles>) -> Result<Executable, SourceError> {
+    let paths = objects.iter().map(|x| x.source.clone()).collect::<Vec<_>>();
+    link(objects).map_err(|e| object_error(&paths, e))
+}
+
+/// Like `link_objects`, but only the dialogues which can be reached from
+/// the roots, which have to be public, are linked. Returns the full names of the dialogues which
+/// were left out too
+pub fn link_objects_reachable(objects : Vec<ObjectFiles>, roots : &[String]) -> Result<(Executable, Vec<String>), SourceError> {
+    let paths = objects.iter().map(|x| x.source.clone()).collect::<Vec<_>>();
+    link_reachable(objects, roots).map_err(|e| object_error(&paths, e))
+}
+
+fn object_error(paths : &[PathBuf], (id, error) : (usize, CompileError)) -> SourceError {
+    // The file may be gone since the objects were compiled
+    let source = fs::read_to_string(&paths[id]).unwrap_or_default();
+    SourceError { path : paths[id].clone(), source, error }
+}
+
+/// Writes the objects as a YAML object file
+pub fn save_object(objects : &ObjectFiles) -> String {
+    dump_yaml(&object_file::objects_into_yaml(objects))
+}
+
+/// Reads the object file written by `save_object`
+pub fn load_object(source : &str) -> Result<ObjectFiles, LoadError> {
+    let yaml = YamlLoader::load_from_str(source)?.pop().ok_or(LoadError::BadObject)?;
+    object_file::parse_yaml_objects(yaml)
+}
+
+fn dump_yaml(yaml : &Yaml) -> String {
+    let mut s = String::new();
+    // Writing into a string can't fail
+    YamlEmitter::new(&mut s).dump(yaml).unwrap();
+    s
+}
+
+/// Writes the executable as YAML assembly: the entry table, the
+/// opcode array and the symbol table. See `disassemble` for the
+/// text assembly which is easier to read and edit
+pub fn save_assembly(exe : &Executable) -> String {
+    let yamls = opcode_saver::executable_into_yaml(exe);
+    yamls.iter().map(dump_yaml).collect::<Vec<_>>().join("\n")
+}
+
+/// Reads the executable written by `save_assembly`. The text assembly
+/// written by `disassemble` is read by `assemble`
+pub fn load_assembly(source : &str) -> Result<Executable, LoadError> {
+    opcode_loader::parse_yaml_executable(YamlLoader::load_from_str(source)?)
+}
+
+/// Writes the executable in the compact binary format
+pub fn save_bytecode(exe : &Executable) -> Vec<u8> {
+    bytecode_saver::executable_into_bytes(exe)
+}
+
+/// Reads the executable written by `save_bytecode`
+pub fn load_bytecode(bytes : &[u8]) -> Result<Executable, LoadError> {
+    bytecode_loader::parse_bytecode_executable(bytes)
+}
+
+/// Reads an executable which is either bytecode, YAML
+/// assembly or text assembly
+pub fn load_executable(bytes : &[u8]) -> Result<Executable, LoadError> {
+    if bytecode_loader::is_bytecode(bytes) { return load_bytecode(bytes) }
+    let source = std::str::from_utf8(bytes).map_err(|_| LoadError::NotUtf8)?;
+    // The YAML emitter always starts the document with `---`
+    if source.starts_with("---") { load_assembly(source) }
+    else { Ok(assemble(source)?) }
+}
+
+/// Writes the snapshot as a save file
+pub fn save_snapshot(snapshot : &Snapshot) -> String {
+    dump_yaml(&save_file::snapshot_into_yaml(snapshot))
+}
+
+/// Reads the snapshot written by `save_snapshot`
+pub fn load_snapshot(source : &str) -> Result<Snapshot, LoadError> {
+    let yaml = YamlLoader::load_from_str(source)?.pop().ok_or(LoadError::BadSnapshot)?;
+    save_file::parse_yaml_snapshot(yaml)
+}
+
+/// Reads a value written the way YAML writes it, e.g.
+/// `10`, `true` or `Bob`
+pub fn parse_value(source : &str) -> Option<Value> {
+    opcode_loader::parse_yaml_value(Yaml::from_str(source))
+}
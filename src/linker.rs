@@ -1,64 +1,185 @@
-use crate::vm::{ BranchLeaf, Instruction };
-use crate::translator::{ BranchPreLeaf, PreInstruction, ObjectFiles };
+use crate::vm::{ BranchLeaf, Instruction, Program };
+use crate::translator::{ BranchPreLeaf, Object, PreInstruction, ObjectFiles, call_target };
+use crate::error::{ CompileError, CompileErrorKind };
+use crate::marked_yaml::Pos;
+
+use std::collections::{ HashMap, HashSet };
 
 use log::debug;
 use linked_hash_map::LinkedHashMap;
 
+/// The linked opcodes together with the addresses
+/// of the dialogues they were made from
+#[derive(Debug, PartialEq, Eq)]
 pub struct Executable {
     pub entry_points : LinkedHashMap<String, usize>,
+    /// All the dialogues, the private ones too. They only
+    /// name the addresses in the errors, so they may be empty
+    pub symbols : LinkedHashMap<String, usize>,
     pub opcodes : Vec<Instruction>,
 }
 
-pub fn link(mut files : ObjectFiles) -> Executable {
-    debug!(target: "linker", "Linking...");
-    let entry_point_ordering = 
-        files.objects.keys()
-        .map(|x| (*x).clone())
-        .collect::<Vec<_>>()
+impl Executable {
+    /// Creates a program starting at the given dialogue. Returns
+    /// `None` if there's no such dialogue
+    pub fn into_program(self, entry_point : &str) -> Option<Program> {
+        let address = *self.entry_points.get(entry_point)?;
+        Some(Program::new(self.opcodes, address).with_names(self.symbols))
+    }
+}
+
+// The address of the dialogue, whether it's `pub`
+// and the index of the file which defines it
+struct Symbol {
+    address : usize,
+    public : bool,
+    file : usize,
+}
+
+// The dialogues of the other modules have to be public
+fn resolve<'a>(symbols : &'a LinkedHashMap<String, Symbol>, modules : &[String], caller : usize, name : &str, pos : Pos) -> Result<&'a Symbol, CompileError> {
+    let full_name = call_target(&modules[caller], name);
+    match symbols.get(&full_name) {
+        Some(x) if x.public || x.file == caller => Ok(x),
+        Some(_) => Err(CompileError::new(CompileErrorKind::PrivateDialogue(full_name), pos)),
+        None => Err(CompileError::new(CompileErrorKind::UnknownDialogue(full_name), pos)),
+    }
+}
+
+// The dialogues which the roots call, the dialogues which those call and
+// so on. The calls which don't resolve are reported later, so here they
+// just lead nowhere
+fn reachable(files : &[ObjectFiles], roots : &[String]) -> HashSet<String> {
+    let objects : HashMap<&str, (&str, &Object)> =
+        files.iter()
+        .flat_map(|file| file.objects.iter().map(move |(name, object)| (name.as_str(), (file.module.as_str(), object))))
+        .collect()
     ;
+    let mut reached = HashSet::new();
+    let mut stack = roots.to_vec();
+    while let Some(name) = stack.pop() {
+        let (module, object) = {
+            match objects.get(name.as_str()) {
+                Some(x) => *x,
+                None => continue,
+            }
+        };
+        if !reached.insert(name) { continue }
+        for pre_opcode in object.code.iter() {
+            if let PreInstruction::UnresolvedCall(x, _) = pre_opcode { stack.push(call_target(module, x)) }
+        }
+    }
+    reached
+}
+
+/// Links the files into one executable. The dialogues are laid out in the
+/// order of the files. Only the `pub` dialogues get into the entry table,
+/// but all of them get into the symbol table.
+/// The error comes with the index of the file it was found in
+pub fn link(files : Vec<ObjectFiles>) -> Result<Executable, (usize, CompileError)> {
+    link_impl(files, None).map(|(exe, _)| exe)
+}
+
+/// Like `link`, but only the dialogues which can be reached from the roots
+/// get into the executable. The roots have to be public dialogues, and the
+/// dialogues which are left out still have to link. Returns the executable together with the full names of the
+/// dialogues which were left out
+pub fn link_reachable(files : Vec<ObjectFiles>, roots : &[String]) -> Result<(Executable, Vec<String>), (usize, CompileError)> {
+    link_impl(files, Some(roots))
+}
+
+fn link_impl(files : Vec<ObjectFiles>, roots : Option<&[String]>) -> Result<(Executable, Vec<String>), (usize, CompileError)> {
+    debug!(target: "linker", "Linking {} files...", files.len());
     
-    debug!(target: "linker", "Creating entry point table...");
+    let modules = files.iter().map(|x| x.module.clone()).collect::<Vec<_>>();
+    // the files without a name aren't modules
+    for (id, module) in modules.iter().enumerate() {
+        if !module.is_empty() && modules[..id].contains(module) {
+            return Err((id, CompileError::new(CompileErrorKind::DuplicateModule(module.clone()), Pos { line : 1, col : 1 })));
+        }
+    }
+
+    let kept = roots.map(|x| reachable(&files, x));
+    let is_kept = |name : &str| kept.as_ref().is_none_or(|x| x.contains(name));
+
+    debug!(target: "linker", "Creating the symbol table...");
     let mut ptr = 0; // this ptr points at the last untouched opcode location
-    // setting up entry points
-    let entry_points : LinkedHashMap<String, usize> =
-        entry_point_ordering.iter()
-        .map(
-            |name| {
-                debug!(target: "linker", "entry point \"{}\" at {}", name, ptr);
-                let my_address = ptr;
-                ptr += files.objects[name].len();
-                (name.clone(), my_address)
+    let mut symbols = LinkedHashMap::new();
+    for (id, file) in files.iter().enumerate() {
+        for (name, object) in file.objects.iter() {
+            if symbols.contains_key(name) {
+                return Err((id, CompileError::new(CompileErrorKind::DuplicateProc(name.clone()), object.pos)));
             }
-        )
-        .collect::<LinkedHashMap<_, _>>()
-    ;
+            debug!(target: "linker", "symbol \"{}\" at {}", name, ptr);
+            symbols.insert(name.clone(), Symbol { address : ptr, public : object.public, file : id });
+            // the dialogues which are left out take no space
+            if is_kept(name) { ptr += object.code.len(); }
+        }
+    }
+
+    // the story starts at the roots, so they have to be public. A root
+    // which isn't there is reported at the start of the first file
+    for root in roots.into_iter().flatten() {
+        match symbols.get(root) {
+            Some(x) if x.public => (),
+            Some(x) => return Err((x.file, CompileError::new(CompileErrorKind::PrivateDialogue(root.clone()), files[x.file].objects[root].pos))),
+            None => return Err((0, CompileError::new(CompileErrorKind::UnknownDialogue(root.clone()), Pos { line : 1, col : 1 }))),
+        }
+    }
+
+    debug!(target: "linker", "Checking the variables...");
+    let declared = files.iter().flat_map(|x| x.variables.iter()).collect::<HashSet<_>>();
+    for (id, file) in files.iter().enumerate() {
+        if let Some((name, pos)) = file.reads.iter().find(|(x, _)| !declared.contains(x)) {
+            return Err((id, CompileError::new(CompileErrorKind::UndeclaredVariable(name.clone()), *pos)));
+        }
+    }
 
     debug!(target: "linker", "Resolving symbols...");
     // resolution
     let mut opcodes = Vec::with_capacity(ptr);
-    for name in entry_point_ordering.iter() {
-        let pre_opcodes = files.objects.remove(name).unwrap();
-        for pre_opcode in pre_opcodes.into_iter() {
+    let objects = 
+        files.into_iter().enumerate()
+        .flat_map(|(id, file)| file.objects.into_iter().map(move |(name, object)| (id, name, object)))
+    ;
+    for (id, name, object) in objects {
+        if !is_kept(&name) {
+            for pre_opcode in object.code.iter() {
+                if let PreInstruction::UnresolvedCall(x, pos) = pre_opcode {
+                    resolve(&symbols, &modules, id, x, *pos).map_err(|e| (id, e))?;
+                }
+            }
+            continue;
+        }
+        let base = symbols[&name].address;
+        for pre_opcode in object.code.into_iter() {
             let opcode = 
                 match pre_opcode {
                     PreInstruction::Ret => Instruction::Ret,
                     PreInstruction::Msg(x) => Instruction::Msg(x),
+                    PreInstruction::Template(x) => Instruction::Template(x),
                     PreInstruction::Wait => Instruction::Wait,
+                    PreInstruction::Push(x) => Instruction::Push(x),
+                    PreInstruction::Load(x) => Instruction::Load(x),
+                    PreInstruction::Store(x) => Instruction::Store(x),
+                    PreInstruction::BinOp(x) => Instruction::BinOp(x),
+                    PreInstruction::UnOp(x) => Instruction::UnOp(x),
                     PreInstruction::Branch(x) => 
                         Instruction::Branch(
                             x.into_iter()
                             .map(
-                                |BranchPreLeaf {option_name, jmp_address}| 
-                                BranchLeaf {option_name, jmp_address : jmp_address + entry_points[name]}
+                                |BranchPreLeaf {option_name, jmp_address, conditional, once, fallback}| 
+                                BranchLeaf {option_name, jmp_address : jmp_address + base, conditional, once, fallback}
                             )
                             .collect()
                         ),
-                    PreInstruction::PushPtr(x) => Instruction::PushPtr(x + entry_points[name]),
-                    PreInstruction::Jmp(x) => Instruction::Jmp(x + entry_points[name]),
-                    PreInstruction::UnresolvedCall(x) => {
-                        match entry_points.get(&x) {
-                            Some(x) => Instruction::Jmp(*x),
-                            None => panic!("Unknown dialogue \"{}\"", x),
+                    PreInstruction::PushPtr(x) => Instruction::PushPtr(x + base),
+                    PreInstruction::Jmp(x) => Instruction::Jmp(x + base),
+                    PreInstruction::JmpIfNot(x) => Instruction::JmpIfNot(x + base),
+                    PreInstruction::UnresolvedCall(x, pos) => {
+                        match resolve(&symbols, &modules, id, &x, pos) {
+                            Ok(x) => Instruction::Jmp(x.address),
+                            Err(e) => return Err((id, e)),
                         }
                     },
                 }
@@ -67,9 +188,26 @@ pub fn link(mut files : ObjectFiles) -> Executable {
         }
     }
 
+    let left_out = symbols.keys().filter(|x| !is_kept(x)).cloned().collect();
+    let entry_points = 
+        symbols.iter()
+        .filter(|(name, x)| x.public && is_kept(name))
+        .map(|(name, x)| (name.clone(), x.address))
+        .collect()
+    ;
+    // the dialogues which are left out start where the next one does
+    let symbols =
+        symbols.into_iter()
+        .filter(|(name, _)| is_kept(name))
+        .map(|(name, x)| (name, x.address))
+        .collect()
+    ;
+
     debug!(target: "linker", "Done linking. {} opcodes processed", opcodes.len());
-    Executable {
+    let exe = Executable {
         entry_points,
+        symbols,
         opcodes,
-    }
+    };
+    Ok((exe, left_out))
 }
@@ -1,50 +1,187 @@
-mod vm;
-mod opcode_saver;
-mod opcode_loader;
-mod linker;
-mod parser;
 mod client;
-mod translator;
 
-use parser::parse_yaml;
-use translator::translate_file;
-use linker::{ Executable, link };
-use vm::Program;
+use texted_adventure::{ Executable, Limits, ObjectFiles, Program, Snapshot, SourceError, Value, check_files, compile_files, compile_files_reachable, compile_object, disassemble, explore, link_objects, link_objects_reachable, load_executable, load_object, load_snapshot, optimize, parse_value, save_bytecode, save_object, story_graph };
 
 use std::fs;
 use std::io;
 use io::Write;
+use std::fmt::Display;
 use std::path::Path;
+use std::process;
+use std::sync::Arc;
 
 use regex::Regex;
-use yaml_rust::emitter::YamlEmitter;
-use yaml_rust::yaml::YamlLoader;
 use clap::clap_app;
 use log::debug;
 
-fn compile_file<P : AsRef<Path>>(path : P) -> Executable {
-        debug!(target: "compile_file", "reading file: \"{}\"", path.as_ref().to_string_lossy());
-        let s = fs::read_to_string(path.as_ref()).unwrap();
+/// Reports the error to the user and shuts the engine down
+fn fail<E : Display>(err : E) -> ! {
+        eprintln!("error: {}", err);
+        process::exit(1)
+}
+
+fn read_file<P : AsRef<Path>>(path : P) -> String {
+        match fs::read_to_string(path.as_ref()) {
+            Ok(s) => s,
+            Err(e) => fail(format!("can't read \"{}\": {}", path.as_ref().to_string_lossy(), e)),
+        }
+}
+
+fn read_bytes<P : AsRef<Path>>(path : P) -> Vec<u8> {
+        match fs::read(path.as_ref()) {
+            Ok(s) => s,
+            Err(e) => fail(format!("can't read \"{}\": {}", path.as_ref().to_string_lossy(), e)),
+        }
+}
+
+// compiles the dialogue files and everything they import into one executable.
+// Only the dialogues the roots can reach are linked, all of them if there are no roots
+fn compile_story(paths : &[&str], roots : &[String]) -> Executable {
+        let re = Regex::new(r#"(.+)\.diag"#).unwrap();
+        for path in paths.iter() {
+            debug!(target: "compile_story", "compiling file: \"{}\"", path);
+            if re.captures(path).is_none() { fail(format!("the dialogue file \"{}\" must have the \".diag\" extension", path)) }
+        }
 
-        let mut res = YamlLoader::load_from_str(&s).unwrap();
-        link(translate_file(parse_yaml(res.pop().unwrap())))
+        if roots.is_empty() { return compile_files(paths).unwrap_or_else(|e| fail_source(e)) }
+        let (exe, left_out) = compile_files_reachable(paths, roots).unwrap_or_else(|e| fail_source(e));
+        warn_left_out(&left_out);
+        exe
 }
 
-fn write_executable<P : AsRef<Path>>(path : P, exe : Executable) {
+fn warn_left_out(left_out : &[String]) {
+        for name in left_out.iter() {
+            eprintln!("warning: the dialogue \"{}\" can't be reached and was left out", name);
+        }
+}
+
+/// Reports the error in the dialogue file and shuts the engine down
+fn fail_source(err : SourceError) -> ! {
+        eprint!("{}", err.render());
+        process::exit(1)
+}
+
+fn write_file<P : AsRef<Path>>(path : P, contents : &[u8]) {
+        debug!(target: "write_file", "Outputting to file: {}", path.as_ref().to_string_lossy());
+        if let Err(e) = fs::write(path.as_ref(), contents) {
+            fail(format!("can't write \"{}\": {}", path.as_ref().to_string_lossy(), e))
+        }
+}
+
+// the object files are linked on their own, so the imports aren't followed
+fn compile_objects(paths : &[&str]) {
+        let re = Regex::new(r#"(.+)\.diag"#).unwrap();
+        for path in paths.iter() {
+            let caps = re.captures(path).unwrap_or_else(|| fail(format!("the dialogue file \"{}\" must have the \".diag\" extension", path)));
+            let objects = compile_object(path).unwrap_or_else(|e| fail_source(e));
+            write_file(format!("{}.obj", &caps[1]), save_object(&objects).as_bytes());
+        }
+}
+
+fn link_files(paths : &[&str], roots : &[String]) -> Executable {
+        let re = Regex::new(r#"(.+)\.obj"#).unwrap();
+        let objects = 
+            paths.iter()
+            .map(
+                |path| {
+                    if re.captures(path).is_none() { fail(format!("the object file \"{}\" must have the \".obj\" extension", path)) }
+                    load_object(&read_file(path)).unwrap_or_else(|e| fail(format!("\"{}\": {}", path, e)))
+                }
+            )
+            .collect::<Vec<ObjectFiles>>()
+        ;
+        if roots.is_empty() { return link_objects(objects).unwrap_or_else(|e| fail_source(e)) }
+        let (exe, left_out) = link_objects_reachable(objects, roots).unwrap_or_else(|e| fail_source(e));
+        warn_left_out(&left_out);
+        exe
+}
+
+fn write_executable<P : AsRef<Path>>(path : P, exe : Executable, binary : bool) {
         debug!(target: "write_executable", "Outputting to file: {}", path.as_ref().to_string_lossy());
-        let mut f = fs::File::create(&path).unwrap();
+        let mut f = match fs::File::create(&path) {
+            Ok(f) => f,
+            Err(e) => fail(format!("can't create \"{}\": {}", path.as_ref().to_string_lossy(), e)),
+        };
+
+        let res = {
+            if binary { f.write_all(&save_bytecode(&exe)) }
+            else { write!(f, "{}", disassemble(&exe)) }
+        };
+        if let Err(e) = res { fail(e) }
+}
+
+fn read_save(path : &Path) -> Option<Snapshot> {
+        if !path.exists() { return None }
+        let file_contents = read_file(path);
+        Some(load_snapshot(&file_contents).unwrap_or_else(|e| fail(e)))
+}
+
+fn parse_step_limit(x : Option<&str>) -> Option<usize> {
+        x.map(|x| x.parse().unwrap_or_else(|_| fail("--step-limit must be a number")))
+}
+
+// parses the `name=value` pairs forwarded with `--set`
+fn parse_variables<'a, I : Iterator<Item = &'a str>>(args : I) -> Vec<(String, Value)> {
+        args.map(
+            |arg| {
+                match arg.split_once('=') {
+                    Some((name, value)) => {
+                        let value = parse_value(value).unwrap_or_else(|| fail(format!("bad value in \"{}\"", arg)));
+                        (name.to_string(), value)
+                    },
+                    None => fail(format!("\"{}\" must look like \"name=value\"", arg)),
+                }
+            }
+        )
+        .collect()
+}
+
+// writes the addresses as ranges, e.g. "3-5, 9"
+fn format_ranges(addresses : &[usize]) -> String {
+        let mut ranges : Vec<(usize, usize)> = Vec::new();
+        for x in addresses.iter() {
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == *x => *end = *x,
+                _ => ranges.push((*x, *x)),
+            }
+        }
+        ranges.iter()
+        .map(|(start, end)| if start == end { start.to_string() } else { format!("{}-{}", start, end) })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
 
-        let yamls = opcode_saver::executable_into_yaml(&exe);
-        let (mut s1, mut s2) = (String::new(), String::new()); 
-        let mut table_emitter = YamlEmitter::new(&mut s1);
-        let mut opcode_emitter = YamlEmitter::new(&mut s2);
-        table_emitter.dump(&yamls[0]).unwrap();
-        opcode_emitter.dump(&yamls[1]).unwrap();
-        
-        write!(f, "{}\n{}", s1, s2).unwrap();
+fn explore_executable(exe : Executable, entry : &str, variables : &[(String, Value)], limits : Limits) {
+        let program = Arc::new(exe.into_program(entry).unwrap_or_else(|| fail(format!("no \"{}\" entry point", entry))));
+        let report = explore(&program, variables, limits);
+
+        println!("paths: {}", report.paths);
+        println!("endings: {}", report.endings.len());
+        for (ending, count) in report.endings.iter() {
+            let ending = if ending.is_empty() { "(nothing printed)".to_string() } else { format!("{:?}", ending) };
+            println!("    {} ({} path(s))", ending, count);
+        }
+        println!("max depth: {} choice(s)", report.max_depth);
+        println!("loops: {}", report.loops);
+        if report.cut_off > 0 { println!("cut off at {} choices: {} path(s)", limits.max_choices, report.cut_off) }
+        if !report.unreachable.is_empty() { println!("unreachable instructions: {}", format_ranges(&report.unreachable)) }
+        for (picked, failure) in report.failures.iter() {
+            let picked = picked.iter().map(|x| format!("{:?}", x)).collect::<Vec<_>>();
+            println!("error: {} (after {})", failure, if picked.is_empty() { "no choices".to_string() } else { picked.join(" -> ") });
+        }
+        if !report.failures.is_empty() { process::exit(1) }
 }
 
-fn run_executable(exe : Executable, force_entry_choice : bool) {
+fn run_executable(exe : Executable, force_entry_choice : bool, save_path : Option<&Path>, variables : &[(String, Value)], step_limit : Option<usize>) {
+        // Continue the saved game if there's one
+        if let Some(snapshot) = save_path.and_then(read_save) {
+            let program = Arc::new(Program::new(exe.opcodes, 0).with_names(exe.symbols));
+            let mut exec = program.resume(snapshot).unwrap_or_else(|e| fail(e));
+            variables.iter().for_each(|(name, value)| exec.set_variable(name, value.clone()));
+            if let Err(e) = client::stdio_client(exec, save_path, step_limit) { fail(e) }
+            return;
+        }
+
         let entry_address = {
            if exe.entry_points.contains_key("main") && !force_entry_choice {
                 exe.entry_points["main"]
@@ -70,9 +207,10 @@ fn run_executable(exe : Executable, force_entry_choice : bool) {
                 id
            }
         };
-        let program = Program::new(exe.opcodes, entry_address);
-        let exec = program.run();
-        client::stdio_client(exec);
+        let program = Arc::new(Program::new(exe.opcodes, entry_address).with_names(exe.symbols));
+        let mut exec = program.run();
+        variables.iter().for_each(|(name, value)| exec.set_variable(name, value.clone()));
+        if let Err(e) = client::stdio_client(exec, save_path, step_limit) { fail(e) }
 }
 
 fn main() {
@@ -84,52 +222,150 @@ fn main() {
         (author: "Jasmine Katzenberg")
         (about: "A small text based game")
         (@subcommand run =>
-            (about: "runs the game in the module which was forwarded to the engine")
+            (about: "runs the game in the module which was forwarded to the engine. Both the assembly and the bytecode are accepted")
             (@arg force_entry_choice: -f --force "forces the engine into entering the entry-point choice mode")
+            (@arg save: -s --save +takes_value "the save file. The game continues from it if it exists. Type \"save\" when picking an option to save")
+            (@arg set: --set +takes_value +multiple_occurrences "sets a story variable before the start, e.g. `--set gold=10`")
+            (@arg step_limit: --("step-limit") +takes_value "stops the story once it runs that many instructions without asking the player anything, e.g. in an endless loop")
             (@arg path: +required "the path to the file")
         )
         (@subcommand compile =>
-            (about: "compiles a file into assembly")
+            (about: "compiles the files, together with the files they import, into assembly. The output is named after the first file")
+            (@arg binary: -b --binary "outputs the compact bytecode (\".bin\") instead of the assembly")
+            (@arg object: -c --object conflicts_with[binary] "compiles every file into an object file (\".obj\") without the files it imports. The object files are put together with `link`")
+            (@arg optimize: -O --optimize conflicts_with[object] "optimizes the jumps and throws away the instructions which are never run")
+            (@arg root: -r --root +takes_value +multiple_occurrences conflicts_with[object] "links only the dialogues which can be reached from the given public dialogue, e.g. `-r main`. Can be repeated")
+            (@arg path: +required +multiple_values "the paths to the files")
+        )
+        (@subcommand check =>
+            (about: "checks the files, together with the files they import, without running them and reports every problem found")
+            (@arg path: +required +multiple_values "the paths to the files")
+        )
+        (@subcommand graph =>
+            (about: "prints the graph of the dialogues, the choices and the calls in the Graphviz DOT language")
+            (@arg mermaid: -m --mermaid "prints a Mermaid flowchart instead")
+            (@arg path: +required +multiple_values "the paths to the files")
+        )
+        (@subcommand explore =>
+            (about: "plays every path through the story and reports the endings, the unreachable instructions and the errors. Both the dialogue files and the executables are accepted")
+            (@arg entry: -e --entry +takes_value "the entry point to start from, \"main\" by default")
+            (@arg set: --set +takes_value +multiple_occurrences "sets a story variable before the start, e.g. `--set gold=10`")
+            (@arg max_choices: --("max-choices") +takes_value "cuts off the paths with more choices (64 by default)")
             (@arg path: +required "the path to the file")
         )
+        (@subcommand link =>
+            (about: "links the object files into assembly. The output is named after the first file")
+            (@arg binary: -b --binary "outputs the compact bytecode (\".bin\") instead of the assembly")
+            (@arg root: -r --root +takes_value +multiple_occurrences "links only the dialogues which can be reached from the given public dialogue, e.g. `-r main`. Can be repeated")
+            (@arg path: +required +multiple_values "the paths to the object files")
+        )
         (@subcommand crun =>
-            (about: "quickly internally compile a dialogue file and run it")
+            (about: "quickly internally compile the dialogue files and run them")
             (@arg force_entry_choice: -f --force "forces the engine into entering the entry-point choice mode")
-            (@arg path: +required "the path to the file")
+            (@arg save: -s --save +takes_value "the save file. The game continues from it if it exists. Type \"save\" when picking an option to save")
+            (@arg set: --set +takes_value +multiple_occurrences "sets a story variable before the start, e.g. `--set gold=10`")
+            (@arg step_limit: --("step-limit") +takes_value "stops the story once it runs that many instructions without asking the player anything, e.g. in an endless loop")
+            (@arg path: +required +multiple_values "the paths to the files")
         )
     ).get_matches();
 
 
     if let Some(matches) = matches.subcommand_matches("compile") {
-        let path = matches.value_of("path").unwrap();
+        let paths = matches.values_of("path").unwrap().collect::<Vec<_>>();
+        if matches.is_present("object") {
+            compile_objects(&paths);
+            return;
+        }
+        let roots = matches.values_of("root").into_iter().flatten().map(str::to_string).collect::<Vec<_>>();
+        let mut exe = compile_story(&paths, &roots);
+        if matches.is_present("optimize") { exe = optimize(exe); }
+
+        // compile_story made sure that the extension is there
         let re = Regex::new(r#"(.+)\.diag"#).unwrap();
-        let caps = re.captures(path).unwrap();
-        
-        let exe = compile_file(path);
+        let caps = re.captures(paths[0]).unwrap();
 
+        let binary = matches.is_present("binary");
         let mut out = String::new();
         out.push_str(&caps[1]);
-        out.push_str(".asm");
-        write_executable(out, exe);
+        out.push_str(if binary { ".bin" } else { ".asm" });
+        write_executable(out, exe, binary);
     } 
 
-    if let Some(matches) = matches.subcommand_matches("run") {
+    if let Some(matches) = matches.subcommand_matches("check") {
+        let paths = matches.values_of("path").unwrap().collect::<Vec<_>>();
+        let problems = check_files(&paths);
+        for problem in problems.iter() {
+            eprint!("{}", problem.render());
+        }
+        if problems.is_empty() { println!("no problems found") }
+        else { fail(format!("{} problem(s) found", problems.len())) }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("graph") {
+        let paths = matches.values_of("path").unwrap().collect::<Vec<_>>();
+        let graph = story_graph(&paths).unwrap_or_else(|e| fail_source(e));
+        if matches.is_present("mermaid") { print!("{}", graph.to_mermaid()) }
+        else { print!("{}", graph.to_dot()) }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("explore") {
         let path = matches.value_of("path").unwrap();
-        let re = Regex::new(r#"(.+)\.asm"#).unwrap();
-        let _ = re.captures(path).unwrap();
+        let exe = {
+            if path.ends_with(".diag") { compile_story(&[path], &[]) }
+            else { load_executable(&read_bytes(path)).unwrap_or_else(|e| fail(e)) }
+        };
+        let mut limits = Limits::default();
+        if let Some(x) = matches.value_of("max_choices") {
+            limits.max_choices = x.parse().unwrap_or_else(|_| fail("--max-choices must be a number"));
+        }
+        explore_executable(
+            exe,
+            matches.value_of("entry").unwrap_or("main"),
+            &parse_variables(matches.values_of("set").into_iter().flatten()),
+            limits,
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("link") {
+        let paths = matches.values_of("path").unwrap().collect::<Vec<_>>();
+        let roots = matches.values_of("root").into_iter().flatten().map(str::to_string).collect::<Vec<_>>();
+        let exe = link_files(&paths, &roots);
 
+        // link_files made sure that the extension is there
+        let re = Regex::new(r#"(.+)\.obj"#).unwrap();
+        let caps = re.captures(paths[0]).unwrap();
 
-        let file_contents = fs::read_to_string(path).unwrap();
-        let yaml = YamlLoader::load_from_str(&file_contents).unwrap();
-        let exe = opcode_loader::parse_yaml_executable(yaml);
-        run_executable(exe, matches.is_present("force_entry_choice"));
+        let binary = matches.is_present("binary");
+        let mut out = String::new();
+        out.push_str(&caps[1]);
+        out.push_str(if binary { ".bin" } else { ".asm" });
+        write_executable(out, exe, binary);
     }
 
-    if let Some(matches) = matches.subcommand_matches("crun") {
+    if let Some(matches) = matches.subcommand_matches("run") {
         let path = matches.value_of("path").unwrap();
-        let re = Regex::new(r#"(.+)\.diag"#).unwrap();
-        let _ = re.captures(path).unwrap();
 
-        run_executable(compile_file(path), matches.is_present("force_entry_choice"));
+        // Either the assembly or the bytecode. The bytecode starts with the magic
+        let file_contents = read_bytes(path);
+        let exe = load_executable(&file_contents).unwrap_or_else(|e| fail(e));
+        run_executable(
+            exe, 
+            matches.is_present("force_entry_choice"), 
+            matches.value_of("save").map(Path::new),
+            &parse_variables(matches.values_of("set").into_iter().flatten()),
+            parse_step_limit(matches.value_of("step_limit")),
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("crun") {
+        let paths = matches.values_of("path").unwrap().collect::<Vec<_>>();
+        let exe = compile_story(&paths, &[]);
+        run_executable(
+            exe, 
+            matches.is_present("force_entry_choice"), 
+            matches.value_of("save").map(Path::new),
+            &parse_variables(matches.values_of("set").into_iter().flatten()),
+            parse_step_limit(matches.value_of("step_limit")),
+        );
     }
 }
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+
+use yaml_rust::parser::{ Event, MarkedEventReceiver, Parser };
+use yaml_rust::scanner::{ Marker, ScanError, TScalarStyle };
+use yaml_rust::yaml::Yaml;
+
+/// A location in the source file. Both the line and
+/// the column start from 1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pos {
+    pub line : usize,
+    pub col : usize,
+}
+
+impl From<Marker> for Pos {
+    fn from(mark : Marker) -> Pos {
+        // yaml-rust counts lines from 1, but columns from 0
+        Pos { line : mark.line(), col : mark.col() + 1 }
+    }
+}
+
+/// A YAML node which remembers where it was written.
+/// Unlike `yaml_rust::Yaml` hashes keep duplicate keys,
+/// so the parser can complain about them.
+#[derive(Clone, Debug)]
+pub enum Node {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    /// Reals and nulls. None of the commands take those
+    Other,
+    Array(Vec<MarkedYaml>),
+    Hash(Vec<(MarkedYaml, MarkedYaml)>),
+    BadValue,
+}
+
+#[derive(Clone, Debug)]
+pub struct MarkedYaml {
+    pub node : Node,
+    pub pos : Pos,
+}
+
+impl MarkedYaml {
+    /// Returns the string if the node is a string
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.node {
+            Node::String(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+// Works pretty much like `yaml_rust::YamlLoader`, but keeps the markers
+struct MarkedLoader {
+    docs : Vec<MarkedYaml>,
+    // (current node, anchor_id) tuple
+    doc_stack : Vec<(MarkedYaml, usize)>,
+    key_stack : Vec<Option<MarkedYaml>>,
+    anchor_map : BTreeMap<usize, MarkedYaml>,
+}
+
+impl MarkedLoader {
+    fn insert_new_node(&mut self, node : (MarkedYaml, usize)) {
+        // valid anchor id starts from 1
+        if node.1 > 0 {
+            self.anchor_map.insert(node.1, node.0.clone());
+        }
+        match self.doc_stack.last_mut() {
+            None => self.doc_stack.push(node),
+            Some((MarkedYaml { node : Node::Array(v), .. }, _)) => v.push(node.0),
+            Some((MarkedYaml { node : Node::Hash(h), .. }, _)) => {
+                let cur_key = self.key_stack.last_mut().unwrap();
+                match cur_key.take() {
+                    // current node is a value
+                    Some(key) => h.push((key, node.0)),
+                    // current node is a key
+                    None => *cur_key = Some(node.0),
+                }
+            },
+            Some(_) => unreachable!(),
+        }
+    }
+}
+
+impl MarkedEventReceiver for MarkedLoader {
+    fn on_event(&mut self, ev : Event, mark : Marker) {
+        let pos = Pos::from(mark);
+        match ev {
+            Event::DocumentEnd => {
+                match self.doc_stack.pop() {
+                    // empty document
+                    None => self.docs.push(MarkedYaml { node : Node::BadValue, pos }),
+                    Some((doc, _)) => self.docs.push(doc),
+                }
+            },
+            Event::SequenceStart(aid) => {
+                self.doc_stack.push((MarkedYaml { node : Node::Array(Vec::new()), pos }, aid));
+            },
+            Event::MappingStart(aid) => {
+                self.doc_stack.push((MarkedYaml { node : Node::Hash(Vec::new()), pos }, aid));
+                self.key_stack.push(None);
+            },
+            Event::SequenceEnd => {
+                let node = self.doc_stack.pop().unwrap();
+                self.insert_new_node(node);
+            },
+            Event::MappingEnd => {
+                self.key_stack.pop().unwrap();
+                let node = self.doc_stack.pop().unwrap();
+                self.insert_new_node(node);
+            },
+            Event::Scalar(v, style, aid, _) => {
+                // The marker of a quoted scalar points at the quote. We'd rather
+                // point at the text, so the offsets inside the string work out
+                let pos = {
+                    match style {
+                        TScalarStyle::SingleQuoted | TScalarStyle::DoubleQuoted => Pos { col : pos.col + 1, ..pos },
+                        _ => pos,
+                    }
+                };
+                let node = {
+                    if style != TScalarStyle::Plain { Node::String(v) }
+                    else {
+                        match Yaml::from_str(&v) {
+                            Yaml::String(x) => Node::String(x),
+                            Yaml::Integer(x) => Node::Integer(x),
+                            Yaml::Boolean(x) => Node::Boolean(x),
+                            Yaml::BadValue => Node::BadValue,
+                            _ => Node::Other,
+                        }
+                    }
+                };
+                self.insert_new_node((MarkedYaml { node, pos }, aid));
+            },
+            Event::Alias(id) => {
+                let node = {
+                    match self.anchor_map.get(&id) {
+                        Some(x) => MarkedYaml { node : x.node.clone(), pos },
+                        None => MarkedYaml { node : Node::BadValue, pos },
+                    }
+                };
+                self.insert_new_node((node, 0));
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Loads all the documents from the string keeping track
+/// of where each node is located.
+pub fn load_from_str(source : &str) -> Result<Vec<MarkedYaml>, ScanError> {
+    let mut loader = MarkedLoader {
+        docs : Vec::new(),
+        doc_stack : Vec::new(),
+        key_stack : Vec::new(),
+        anchor_map : BTreeMap::new(),
+    };
+    let mut parser = Parser::new(source.chars());
+    parser.load(&mut loader, true)?;
+    Ok(loader.docs)
+}
@@ -0,0 +1,200 @@
+// The object file holds the translated dialogues of one module:
+//
+//   source          the dialogue file the objects were made from
+//   module          the module's name
+//   variables       the variables which the `set`s write and the `extern`s
+//   reads           [name, line, col] for every variable an expression or
+//                   a text reads. The older files call it `template_reads`
+//                   and list the texts only
+//   objects         the dialogues keyed with their full names:
+//                   `at` is the [line, col] of the name, `pub` tells
+//                   if it's exported and `code` holds the opcodes
+//
+// The opcodes are written the way the YAML assembly writes them. Their
+// addresses are relative to the dialogue's start, so the linker only has
+// to add the dialogue's address to them. The calls stay unresolved and
+// are written as `{call: name, at: [line, col]}`
+
+use crate::translator::{ BranchPreLeaf, Object, ObjectFiles, PreInstruction };
+use crate::vm::{ BranchLeaf, Instruction };
+use crate::marked_yaml::Pos;
+use crate::error::LoadError;
+use crate::opcode_saver::opcode_into_yaml;
+use crate::opcode_loader::parse_yaml_opcode_impl;
+
+use std::path::PathBuf;
+
+use yaml_rust::yaml::Yaml;
+
+fn pos_into_yaml(pos : Pos) -> Vec<Yaml> {
+    vec![Yaml::Integer(pos.line as i64), Yaml::Integer(pos.col as i64)]
+}
+
+fn parse_pos(yaml : &[Yaml]) -> Option<Pos> {
+    match yaml {
+        [Yaml::Integer(line), Yaml::Integer(col)] if *line > 0 && *col > 0 => Some(Pos { line : *line as usize, col : *col as usize }),
+        _ => None,
+    }
+}
+
+fn pre_opcode_into_yaml(pre_opcode : &PreInstruction) -> Yaml {
+    let opcode = {
+        match pre_opcode {
+            PreInstruction::UnresolvedCall(name, pos) => {
+                return Yaml::Hash(
+                    vec![
+                        (Yaml::String("call".to_string()), Yaml::String(name.clone())),
+                        (Yaml::String("at".to_string()), Yaml::Array(pos_into_yaml(*pos))),
+                    ]
+                    .into_iter().collect()
+                )
+            },
+            PreInstruction::Ret => Instruction::Ret,
+            PreInstruction::Msg(x) => Instruction::Msg(x.clone()),
+            PreInstruction::Template(x) => Instruction::Template(x.clone()),
+            PreInstruction::Jmp(x) => Instruction::Jmp(*x),
+            PreInstruction::JmpIfNot(x) => Instruction::JmpIfNot(*x),
+            PreInstruction::Wait => Instruction::Wait,
+            PreInstruction::Branch(x) =>
+                Instruction::Branch(
+                    x.iter()
+                    .map(
+                        |BranchPreLeaf {option_name, jmp_address, conditional, once, fallback}|
+                        BranchLeaf {option_name : option_name.clone(), jmp_address : *jmp_address, conditional : *conditional, once : *once, fallback : *fallback}
+                    )
+                    .collect()
+                ),
+            PreInstruction::PushPtr(x) => Instruction::PushPtr(*x),
+            PreInstruction::Push(x) => Instruction::Push(x.clone()),
+            PreInstruction::Load(x) => Instruction::Load(x.clone()),
+            PreInstruction::Store(x) => Instruction::Store(x.clone()),
+            PreInstruction::BinOp(x) => Instruction::BinOp(*x),
+            PreInstruction::UnOp(x) => Instruction::UnOp(*x),
+        }
+    };
+    opcode_into_yaml(&opcode)
+}
+
+fn parse_yaml_pre_opcode(yaml : Yaml) -> Option<PreInstruction> {
+    if let Some(name) = yaml["call"].as_str() {
+        return Some(PreInstruction::UnresolvedCall(name.to_string(), parse_pos(yaml["at"].as_vec()?)?))
+    }
+    let pre_opcode = {
+        match parse_yaml_opcode_impl(yaml)? {
+            Instruction::Ret => PreInstruction::Ret,
+            Instruction::Msg(x) => PreInstruction::Msg(x),
+            Instruction::Template(x) => PreInstruction::Template(x),
+            Instruction::Jmp(x) => PreInstruction::Jmp(x),
+            Instruction::JmpIfNot(x) => PreInstruction::JmpIfNot(x),
+            Instruction::Wait => PreInstruction::Wait,
+            Instruction::Branch(x) =>
+                PreInstruction::Branch(
+                    x.into_iter()
+                    .map(
+                        |BranchLeaf {option_name, jmp_address, conditional, once, fallback}|
+                        BranchPreLeaf {option_name, jmp_address, conditional, once, fallback}
+                    )
+                    .collect()
+                ),
+            Instruction::PushPtr(x) => PreInstruction::PushPtr(x),
+            Instruction::Push(x) => PreInstruction::Push(x),
+            Instruction::Load(x) => PreInstruction::Load(x),
+            Instruction::Store(x) => PreInstruction::Store(x),
+            Instruction::BinOp(x) => PreInstruction::BinOp(x),
+            Instruction::UnOp(x) => PreInstruction::UnOp(x),
+        }
+    };
+    Some(pre_opcode)
+}
+
+pub fn objects_into_yaml(files : &ObjectFiles) -> Yaml {
+    let objects =
+        files.objects.iter()
+        .map(
+            |(name, object)| {
+                let object =
+                    vec![
+                        (Yaml::String("at".to_string()), Yaml::Array(pos_into_yaml(object.pos))),
+                        (Yaml::String("pub".to_string()), Yaml::Boolean(object.public)),
+                        (Yaml::String("code".to_string()), Yaml::Array(object.code.iter().map(pre_opcode_into_yaml).collect())),
+                    ]
+                    .into_iter().collect()
+                ;
+                (Yaml::String(name.clone()), Yaml::Hash(object))
+            }
+        )
+        .collect()
+    ;
+    Yaml::Hash(
+        vec![
+            (Yaml::String("source".to_string()), Yaml::String(files.source.to_string_lossy().into_owned())),
+            (Yaml::String("module".to_string()), Yaml::String(files.module.clone())),
+            (
+                Yaml::String("variables".to_string()),
+                Yaml::Array(files.variables.iter().map(|x| Yaml::String(x.clone())).collect())
+            ),
+            (
+                Yaml::String("reads".to_string()),
+                Yaml::Array(
+                    files.reads.iter()
+                    .map(
+                        |(name, pos)| {
+                            let mut read = vec![Yaml::String(name.clone())];
+                            read.extend(pos_into_yaml(*pos));
+                            Yaml::Array(read)
+                        }
+                    )
+                    .collect()
+                )
+            ),
+            (Yaml::String("objects".to_string()), Yaml::Hash(objects)),
+        ]
+        .into_iter().collect()
+    )
+}
+
+pub fn parse_yaml_objects(yaml : Yaml) -> Result<ObjectFiles, LoadError> {
+    let parse = || {
+        let source = PathBuf::from(yaml["source"].as_str()?);
+        let module = yaml["module"].as_str()?.to_string();
+        let variables =
+            yaml["variables"].as_vec()?
+            .iter()
+            .map(|x| Some(x.as_str()?.to_string()))
+            .collect::<Option<_>>()?
+        ;
+        let reads =
+            yaml["reads"].as_vec().or_else(|| yaml["template_reads"].as_vec())?
+            .iter()
+            .map(
+                |x| {
+                    match x.as_vec()?.split_first() {
+                        Some((name, pos)) => Some((name.as_str()?.to_string(), parse_pos(pos)?)),
+                        None => None,
+                    }
+                }
+            )
+            .collect::<Option<_>>()?
+        ;
+        let objects =
+            yaml["objects"].as_hash()?
+            .iter()
+            .map(
+                |(name, object)| {
+                    let pos = parse_pos(object["at"].as_vec()?)?;
+                    let public = object["pub"].as_bool()?;
+                    let code =
+                        object["code"].as_vec()?
+                        .iter()
+                        .map(|x| parse_yaml_pre_opcode(x.clone()))
+                        .collect::<Option<_>>()?
+                    ;
+                    Some((name.as_str()?.to_string(), Object { pos, code, public }))
+                }
+            )
+            .collect::<Option<_>>()?
+        ;
+        Some(ObjectFiles { source, module, variables, reads, objects })
+    };
+    parse().ok_or(LoadError::BadObject)
+}
@@ -1,69 +1,156 @@
-use crate::vm::{ Instruction, BranchLeaf };
+use crate::vm::{ Instruction, BranchLeaf, BinOp, UnOp, TemplatePiece, Value };
 use crate::linker::Executable;
+use crate::error::LoadError;
 
 use yaml_rust::yaml::Yaml;
 use linked_hash_map::LinkedHashMap;
 
-pub fn parse_yaml_opcode_impl(ast : Yaml) -> Instruction {
+pub fn parse_yaml_value(ast : Yaml) -> Option<Value> {
+    match ast {
+        Yaml::Integer(x) => Some(Value::Int(x)),
+        Yaml::Boolean(x) => Some(Value::Bool(x)),
+        Yaml::String(x) => Some(Value::Str(x)),
+        _ => None,
+    }
+}
+
+pub fn parse_bin_op_name(name : &str) -> Option<BinOp> {
+    match name {
+        "add" => Some(BinOp::Add),
+        "sub" => Some(BinOp::Sub),
+        "mul" => Some(BinOp::Mul),
+        "div" => Some(BinOp::Div),
+        "rem" => Some(BinOp::Rem),
+        "eq" => Some(BinOp::Eq),
+        "ne" => Some(BinOp::Ne),
+        "lt" => Some(BinOp::Lt),
+        "le" => Some(BinOp::Le),
+        "gt" => Some(BinOp::Gt),
+        "ge" => Some(BinOp::Ge),
+        _ => None,
+    }
+}
+
+pub fn parse_un_op_name(name : &str) -> Option<UnOp> {
+    match name {
+        "not" => Some(UnOp::Not),
+        "neg" => Some(UnOp::Neg),
+        _ => None,
+    }
+}
+
+// A missing flag is false
+fn parse_yaml_flag(ast : &Yaml) -> Option<bool> {
+    match ast {
+        Yaml::Boolean(x) => Some(*x),
+        Yaml::BadValue => Some(false),
+        _ => None,
+    }
+}
+
+// Either `name: address` or the full form with `option`,
+// `jmp` and the `conditional`, `once` and `fallback` flags
+fn parse_yaml_branch_leaf(ast : Yaml) -> Option<BranchLeaf> {
     match ast {
-        Yaml::String(x) if x.trim() == "wait" => Instruction::Wait,
-        Yaml::String(x) if x.trim() == "ret" => Instruction::Ret,
         Yaml::Hash(mut map) if map.len() == 1 => {
             match map.pop_back() {
-                Some((Yaml::String(cmd), Yaml::String(msg))) if cmd.trim() == "msg" => Instruction::Msg(msg),
-                Some((Yaml::String(cmd), Yaml::Integer(place))) if cmd.trim() == "jmp" && place >= 0 => Instruction::Jmp(place as usize),
-                Some((Yaml::String(cmd), Yaml::Integer(place))) if cmd.trim() == "push_ptr" && place >= 0 => Instruction::PushPtr(place as usize),
+                Some((Yaml::String(option_name), Yaml::Integer(jmp_address))) if jmp_address >= 0 => 
+                    Some(BranchLeaf { option_name, jmp_address : jmp_address as usize, conditional : false, once : false, fallback : false }),
+                _ => None,
+            }
+        },
+        Yaml::Hash(_) => {
+            let option_name = ast["option"].as_str()?.to_string();
+            let jmp_address = {
+                match ast["jmp"] {
+                    Yaml::Integer(x) if x >= 0 => x as usize,
+                    _ => return None,
+                }
+            };
+            let conditional = parse_yaml_flag(&ast["conditional"])?;
+            let once = parse_yaml_flag(&ast["once"])?;
+            let fallback = parse_yaml_flag(&ast["fallback"])?;
+            Some(BranchLeaf { option_name, jmp_address, conditional, once, fallback })
+        },
+        _ => None,
+    }
+}
+
+pub fn parse_yaml_opcode_impl(ast : Yaml) -> Option<Instruction> {
+    match ast {
+        Yaml::String(x) if x.trim() == "wait" => Some(Instruction::Wait),
+        Yaml::String(x) if x.trim() == "ret" => Some(Instruction::Ret),
+        Yaml::Hash(mut map) if map.len() == 1 => {
+            match map.pop_back() {
+                Some((Yaml::String(cmd), Yaml::String(msg))) if cmd.trim() == "msg" => Some(Instruction::Msg(msg)),
+                Some((Yaml::String(cmd), Yaml::Array(pieces))) if cmd.trim() == "template" => {
+                    let pieces =
+                        pieces.into_iter()
+                        .map(
+                            |x| {
+                                match x {
+                                    Yaml::String(x) => Some(TemplatePiece::Text(x)),
+                                    Yaml::Null => Some(TemplatePiece::Value),
+                                    _ => None,
+                                }
+                            }
+                        )
+                        .collect::<Option<_>>()?
+                    ;
+                    Some(Instruction::Template(pieces))
+                },
+                Some((Yaml::String(cmd), Yaml::Integer(place))) if cmd.trim() == "jmp" && place >= 0 => Some(Instruction::Jmp(place as usize)),
+                Some((Yaml::String(cmd), Yaml::Integer(place))) if cmd.trim() == "push_ptr" && place >= 0 => Some(Instruction::PushPtr(place as usize)),
+                Some((Yaml::String(cmd), Yaml::Integer(place))) if cmd.trim() == "jmp_if_not" && place >= 0 => Some(Instruction::JmpIfNot(place as usize)),
+                Some((Yaml::String(cmd), Yaml::String(name))) if cmd.trim() == "load" => Some(Instruction::Load(name)),
+                Some((Yaml::String(cmd), Yaml::String(name))) if cmd.trim() == "store" => Some(Instruction::Store(name)),
+                Some((Yaml::String(cmd), Yaml::String(op))) if cmd.trim() == "bin_op" => Some(Instruction::BinOp(parse_bin_op_name(op.trim())?)),
+                Some((Yaml::String(cmd), Yaml::String(op))) if cmd.trim() == "un_op" => Some(Instruction::UnOp(parse_un_op_name(op.trim())?)),
+                Some((Yaml::String(cmd), value)) if cmd.trim() == "push" => Some(Instruction::Push(parse_yaml_value(value)?)),
                 Some((Yaml::String(cmd), Yaml::Array(options))) if cmd.trim() == "choose" => {
                     let branches =
-                    options.into_iter()
-                    .map(
-                        |x| {
-                            match x {
-                                Yaml::Hash(mut map) if map.len() == 1 => {
-                                    match map.pop_back() {
-                                        Some((Yaml::String(option_name), Yaml::Integer(jmp_address))) if jmp_address > 0 => BranchLeaf { option_name, jmp_address : jmp_address as usize },
-                                        _ => panic!("In the choice map the key must be a string and the value must be an array"),
-                                    }
-                                },
-                                _ => panic!("the choice branch must be a hash with one key-value pair"),
-                            }
-                        }
-                    ).collect();
-                    Instruction::Branch(branches)
+                        options.into_iter()
+                        .map(parse_yaml_branch_leaf)
+                        .collect::<Option<_>>()?
+                    ;
+                    Some(Instruction::Branch(branches))
                 },
-                _ => panic!("The command doesn't satisfy an possible format"),
+                _ => None,
             }
         },
-        _ => panic!(),
+        _ => None,
     }
 }
 
-pub fn parse_yaml_opcode(yaml_ast : Yaml) -> Vec<Instruction> {
+pub fn parse_yaml_opcode(yaml_ast : Yaml) -> Result<Vec<Instruction>, LoadError> {
     if let Yaml::Array(instrs) = yaml_ast {
         instrs.into_iter()
-        .map(&parse_yaml_opcode_impl).collect()
-    } else { panic!("The file's root must be an array") }
+        .enumerate()
+        .map(|(i, x)| parse_yaml_opcode_impl(x).ok_or(LoadError::BadOpcode(i))).collect()
+    } else { Err(LoadError::OpcodesNotArray) }
 }
 
-pub fn parse_yaml_entry_table(yaml_ast : Yaml) -> LinkedHashMap<String, usize> {
+pub fn parse_yaml_entry_table(yaml_ast : Yaml) -> Result<LinkedHashMap<String, usize>, LoadError> {
     if let Yaml::Hash(entries) = yaml_ast {
         entries.into_iter()
         .map(
             |(k, v)| {
                 match (k, v) {
-                    (Yaml::String(name), Yaml::Integer(address)) => (name, address as usize),
-                    _ => panic!("the entry table syntax is not satisfied"),
+                    (Yaml::String(name), Yaml::Integer(address)) if address >= 0 => Ok((name, address as usize)),
+                    _ => Err(LoadError::BadEntry),
                 }
             }
         ).collect()
-    } else { panic!("The file's root must be a hashmap") }
+    } else { Err(LoadError::EntryTableNotHash) }
 }
 
-pub fn parse_yaml_executable(mut file : Vec<Yaml>) -> Executable {
+pub fn parse_yaml_executable(mut file : Vec<Yaml>) -> Result<Executable, LoadError> {
     // The first block is the entry table
     // The second block is the opcode array
-    if file.len() < 2 { panic!("Not all blocks are present") }
-    let opcodes = parse_yaml_opcode(file.pop().unwrap());
-    let entry_points = parse_yaml_entry_table(file.pop().unwrap());
-    Executable { opcodes, entry_points }
+    // The third block is the symbol table, which the older files don't have
+    if file.len() < 2 { return Err(LoadError::MissingBlocks) }
+    let symbols = if file.len() > 2 { parse_yaml_entry_table(file.remove(2))? } else { LinkedHashMap::new() };
+    let opcodes = parse_yaml_opcode(file.remove(1))?;
+    let entry_points = parse_yaml_entry_table(file.remove(0))?;
+    Ok(Executable { opcodes, entry_points, symbols })
 }
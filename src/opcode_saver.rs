@@ -1,41 +1,109 @@
-use crate::vm::{ Instruction, BranchLeaf };
+use crate::vm::{ Instruction, BranchLeaf, BinOp, UnOp, TemplatePiece, Value };
 use crate::linker::Executable;
 
 use yaml_rust::yaml::Yaml;
 use linked_hash_map::LinkedHashMap;
 
+pub fn value_into_yaml(value : &Value) -> Yaml {
+    match value {
+        Value::Int(x) => Yaml::Integer(*x),
+        Value::Bool(x) => Yaml::Boolean(*x),
+        Value::Str(x) => Yaml::String(x.clone()),
+    }
+}
+
+pub fn bin_op_name(op : BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "add",
+        BinOp::Sub => "sub",
+        BinOp::Mul => "mul",
+        BinOp::Div => "div",
+        BinOp::Rem => "rem",
+        BinOp::Eq => "eq",
+        BinOp::Ne => "ne",
+        BinOp::Lt => "lt",
+        BinOp::Le => "le",
+        BinOp::Gt => "gt",
+        BinOp::Ge => "ge",
+    }
+}
+
+pub fn un_op_name(op : UnOp) -> &'static str {
+    match op {
+        UnOp::Not => "not",
+        UnOp::Neg => "neg",
+    }
+}
+
+// A plain option is just `name: address`. An option with
+// flags has to spell everything out. Only the set flags are written
+fn branch_leaf_into_yaml(leaf : &BranchLeaf) -> Yaml {
+    let BranchLeaf { option_name, jmp_address, conditional, once, fallback } = leaf;
+    let map = {
+        if *conditional || *once || *fallback {
+            let flags = 
+                vec![("conditional", *conditional), ("once", *once), ("fallback", *fallback)].into_iter()
+                .filter(|(_, x)| *x)
+                .map(|(name, _)| (Yaml::String(name.to_string()), Yaml::Boolean(true)))
+            ;
+            vec![
+                (Yaml::String("option".to_string()), Yaml::String(option_name.clone())),
+                (Yaml::String("jmp".to_string()), Yaml::Integer(*jmp_address as i64)),
+            ]
+            .into_iter().chain(flags).collect()
+        } else {
+            vec![(Yaml::String(option_name.clone()), Yaml::Integer(*jmp_address as i64))]
+        }
+    };
+    Yaml::Hash(map.into_iter().collect())
+}
+
+pub fn opcode_into_yaml(opcode : &Instruction) -> Yaml {
+    match opcode {
+        Instruction::Ret => Yaml::String("ret".to_string()),
+        Instruction::Wait => Yaml::String("wait".to_string()),
+        Instruction::Jmp(x) => Yaml::Hash(vec![(Yaml::String("jmp".to_string()), Yaml::Integer(*x as i64))].into_iter().collect()),
+        Instruction::Msg(x) => Yaml::Hash(vec![(Yaml::String("msg".to_string()), Yaml::String(x.to_string()))].into_iter().collect()),
+        Instruction::Template(pieces) => {
+            // The text is written as strings and the values as nulls
+            let pieces =
+                pieces.iter()
+                .map(
+                    |x| {
+                        match x {
+                            TemplatePiece::Text(x) => Yaml::String(x.clone()),
+                            TemplatePiece::Value => Yaml::Null,
+                        }
+                    }
+                )
+                .collect()
+            ;
+            Yaml::Hash(vec![(Yaml::String("template".to_string()), Yaml::Array(pieces))].into_iter().collect())
+        },
+        Instruction::PushPtr(x) => Yaml::Hash(vec![(Yaml::String("push_ptr".to_string()), Yaml::Integer(*x as i64))].into_iter().collect()),
+        Instruction::Push(x) => Yaml::Hash(vec![(Yaml::String("push".to_string()), value_into_yaml(x))].into_iter().collect()),
+        Instruction::JmpIfNot(x) => Yaml::Hash(vec![(Yaml::String("jmp_if_not".to_string()), Yaml::Integer(*x as i64))].into_iter().collect()),
+        Instruction::Load(x) => Yaml::Hash(vec![(Yaml::String("load".to_string()), Yaml::String(x.clone()))].into_iter().collect()),
+        Instruction::BinOp(x) => Yaml::Hash(vec![(Yaml::String("bin_op".to_string()), Yaml::String(bin_op_name(*x).to_string()))].into_iter().collect()),
+        Instruction::UnOp(x) => Yaml::Hash(vec![(Yaml::String("un_op".to_string()), Yaml::String(un_op_name(*x).to_string()))].into_iter().collect()),
+        Instruction::Store(x) => Yaml::Hash(vec![(Yaml::String("store".to_string()), Yaml::String(x.clone()))].into_iter().collect()),
+        Instruction::Branch(branches) =>
+            Yaml::Hash(
+                vec![(
+                    Yaml::String("choose".to_string()),
+                    Yaml::Array(
+                        branches.iter()
+                        .map(branch_leaf_into_yaml)
+                        .collect()
+                    )
+                )].into_iter().collect()
+            )
+        ,
+    }
+}
+
 pub fn opcodes_into_yaml(opcodes : &[Instruction]) -> Yaml {
-    let opcode_array : Vec<Yaml> =
-        opcodes.iter()
-        .map(
-            |x| {
-                match x {
-                    Instruction::Ret => Yaml::String("ret".to_string()),
-                    Instruction::Wait => Yaml::String("wait".to_string()),
-                    Instruction::Jmp(x) => Yaml::Hash(vec![(Yaml::String("jmp".to_string()), Yaml::Integer(*x as i64))].into_iter().collect()),
-                    Instruction::Msg(x) => Yaml::Hash(vec![(Yaml::String("msg".to_string()), Yaml::String(x.to_string()))].into_iter().collect()),
-                    Instruction::PushPtr(x) => Yaml::Hash(vec![(Yaml::String("push_ptr".to_string()), Yaml::Integer(*x as i64))].into_iter().collect()),
-                    Instruction::Branch(branches) =>
-                        Yaml::Hash(
-                            vec![(
-                                Yaml::String("choose".to_string()),
-                                Yaml::Array(
-                                    branches.iter()
-                                    .map(
-                                        |BranchLeaf { jmp_address, option_name }|
-                                        Yaml::Hash(vec![(Yaml::String(option_name.clone()), Yaml::Integer(*jmp_address as i64))].into_iter().collect())
-                                    )
-                                    .collect()
-                                )
-                            )].into_iter().collect()
-                        )
-                    ,
-                }
-            }
-        )
-        .collect()
-    ;
-    Yaml::Array(opcode_array)
+    Yaml::Array(opcodes.iter().map(opcode_into_yaml).collect())
 }
 
 pub fn entry_points_into_yaml(opcodes : &LinkedHashMap<String, usize>) -> Yaml {
@@ -54,5 +122,5 @@ pub fn entry_points_into_yaml(opcodes : &LinkedHashMap<String, usize>) -> Yaml {
 }
 
 pub fn executable_into_yaml(exe : &Executable) -> Vec<Yaml> {
-    vec![entry_points_into_yaml(&exe.entry_points), opcodes_into_yaml(&exe.opcodes)]
+    vec![entry_points_into_yaml(&exe.entry_points), opcodes_into_yaml(&exe.opcodes), entry_points_into_yaml(&exe.symbols)]
 }
@@ -0,0 +1,155 @@
+// A peephole pass over the linked opcodes. The translator leaves jumps to
+// jumps behind (the end of an `if` jumps to the end of the choice, which
+// jumps past the choice) and the `ret`s after the tail calls, which never
+// run. The pass:
+//
+//   1. points every jump straight at the end of its jump chain
+//   2. turns the jumps to a `ret` into a `ret`
+//   3. throws the instructions which can't be reached from the entry
+//      points away, together with the jumps to the very next instruction
+//   4. renumbers the addresses. The dialogues which lost all their code
+//      leave the symbol table
+//
+// The program does exactly what it did before, but the snapshots of the
+// old program don't fit the new one
+
+use crate::linker::Executable;
+use crate::vm::Instruction;
+
+use std::collections::HashSet;
+
+use log::debug;
+
+// Follows the chain of jumps. A chain which goes around in
+// circles is left alone, since it doesn't end anywhere
+fn thread(opcodes : &[Instruction], address : usize) -> usize {
+    let mut visited = HashSet::new();
+    let mut res = address;
+    while let Some(Instruction::Jmp(x)) = opcodes.get(res) {
+        if !visited.insert(res) { return address }
+        res = *x;
+    }
+    res
+}
+
+// The addresses the VM may go to after the instruction
+pub(crate) fn successors(opcodes : &[Instruction], address : usize) -> Vec<usize> {
+    match &opcodes[address] {
+        Instruction::Ret => vec![],
+        Instruction::Jmp(x) => vec![*x],
+        Instruction::JmpIfNot(x) => vec![*x, address + 1],
+        // the branch goes on in its options only
+        Instruction::Branch(leaves) => leaves.iter().map(|x| x.jmp_address).collect(),
+        // the pointer is where the called dialogue returns
+        Instruction::PushPtr(x) => vec![*x, address + 1],
+        _ => vec![address + 1],
+    }
+}
+
+/// Optimizes the executable. The entry points stay the same
+pub fn optimize(exe : Executable) -> Executable {
+    let Executable { entry_points, symbols, mut opcodes } = exe;
+    let before = opcodes.len();
+
+    // threading. The jumps are followed in the old opcodes,
+    // so every jump is threaded the same way
+    let threaded =
+        opcodes.iter()
+        .map(
+            |opcode| {
+                match opcode {
+                    Instruction::Jmp(x) => Instruction::Jmp(thread(&opcodes, *x)),
+                    Instruction::JmpIfNot(x) => Instruction::JmpIfNot(thread(&opcodes, *x)),
+                    Instruction::PushPtr(x) => Instruction::PushPtr(thread(&opcodes, *x)),
+                    Instruction::Branch(leaves) => {
+                        let mut leaves = leaves.clone();
+                        leaves.iter_mut().for_each(|x| x.jmp_address = thread(&opcodes, x.jmp_address));
+                        Instruction::Branch(leaves)
+                    },
+                    x => x.clone(),
+                }
+            }
+        )
+        .collect::<Vec<_>>()
+    ;
+    opcodes = threaded;
+
+    // jumping to a `ret` is returning
+    for i in 0..opcodes.len() {
+        if let Instruction::Jmp(x) = opcodes[i] {
+            if let Some(Instruction::Ret) = opcodes.get(x) { opcodes[i] = Instruction::Ret; }
+        }
+    }
+
+    let mut reached = vec![false; opcodes.len()];
+    let mut stack = entry_points.values().copied().collect::<Vec<_>>();
+    while let Some(address) = stack.pop() {
+        // running past the end is the VM's problem
+        match reached.get_mut(address) {
+            Some(x) if !*x => *x = true,
+            _ => continue,
+        }
+        stack.extend(successors(&opcodes, address));
+    }
+
+    // A jump to the next instruction which stays does nothing. Its new
+    // address is the one of that instruction, i.e. where it jumps. The
+    // jumps which are still aimed at jumps go around in circles, so they stay
+    let mut kept = reached.clone();
+    for i in 0..opcodes.len() {
+        if let Instruction::Jmp(x) = opcodes[i] {
+            let useless = x > i && (i + 1..x).all(|j| !kept[j]) && !matches!(opcodes.get(x), Some(Instruction::Jmp(_)));
+            if useless { kept[i] = false; }
+        }
+    }
+
+    // the amount of the instructions which stay before the address
+    let mut new_address = Vec::with_capacity(opcodes.len() + 1);
+    let mut count = 0;
+    for x in kept.iter() {
+        new_address.push(count);
+        if *x { count += 1; }
+    }
+    new_address.push(count);
+    // the addresses past the end stay past the end
+    let renumber = |x : usize| new_address.get(x).copied().unwrap_or_else(|| x - (opcodes.len() - count));
+
+    let opcodes =
+        opcodes.iter().zip(kept.iter())
+        .filter(|(_, kept)| **kept)
+        .map(
+            |(opcode, _)| {
+                match opcode {
+                    Instruction::Jmp(x) => Instruction::Jmp(renumber(*x)),
+                    Instruction::JmpIfNot(x) => Instruction::JmpIfNot(renumber(*x)),
+                    Instruction::PushPtr(x) => Instruction::PushPtr(renumber(*x)),
+                    Instruction::Branch(leaves) => {
+                        let mut leaves = leaves.clone();
+                        leaves.iter_mut().for_each(|x| x.jmp_address = renumber(x.jmp_address));
+                        Instruction::Branch(leaves)
+                    },
+                    x => x.clone(),
+                }
+            }
+        )
+        .collect::<Vec<_>>()
+    ;
+    let entry_points =
+        entry_points.into_iter()
+        .map(|(name, x)| (name, renumber(x)))
+        .collect()
+    ;
+    // such a dialogue would start where the next one does
+    let mut starts = symbols.values().copied().collect::<Vec<_>>();
+    starts.sort_unstable();
+    let end = |x : usize| starts.iter().copied().find(|y| *y > x).unwrap_or(before);
+    let symbols =
+        symbols.into_iter()
+        .filter(|(_, x)| renumber(*x) < renumber(end(*x)))
+        .map(|(name, x)| (name, renumber(x)))
+        .collect()
+    ;
+
+    debug!(target: "optimizer", "Done optimizing. {} opcodes left out of {}", opcodes.len(), before);
+    Executable { entry_points, symbols, opcodes }
+}
@@ -1,69 +1,346 @@
-use yaml_rust::yaml::Yaml;
+use crate::error::{ CompileError, CompileErrorKind };
+use crate::marked_yaml::{ MarkedYaml, Node, Pos };
+use crate::vm::Value;
+use crate::expr::{ Expr, TemplatePart, parse_expr, parse_template };
+
 use linked_hash_map::LinkedHashMap;
 
 /// The abstract syntax tree of a dialogue
-pub enum Ast {
+pub enum AstKind {
     Msg(String),
-    Choice(Vec<(String, Vec<Ast>)>),
+    /// A message with expressions in it. The position is the one of the text
+    Template(Vec<TemplatePart>, Pos),
+    Choice(Vec<ChoiceBranch>),
     Wait,
     Call(String),
+    Set(Vec<(String, Expr)>),
+    If(Expr, Vec<Ast>, Vec<Ast>),
+}
+
+/// An option of a `choose`. The option is only offered
+/// when its `when` condition holds
+pub struct ChoiceBranch {
+    pub name : String,
+    /// Where the option's name was written
+    pub pos : Pos,
+    pub code : Vec<Ast>,
+    pub when : Option<Expr>,
+    /// The option disappears after it's picked
+    pub once : bool,
+    /// The option is taken automatically when nothing else is left
+    pub fallback : bool,
+}
+
+/// An AST node together with the place where it
+/// was written in the dialogue file
+pub struct Ast {
+    pub kind : AstKind,
+    pub pos : Pos,
+}
+
+/// A dialogue together with the place where its name was written
+pub struct Proc {
+    pub pos : Pos,
+    pub code : Vec<Ast>,
+    /// The dialogue is listed under the `pub` key, so the other
+    /// modules can call it. `main` is always public
+    pub public : bool,
 }
 
 /// The representation of a dialogue file
 pub struct File {
-    pub procs : LinkedHashMap<String, Vec<Ast>>,
+    pub procs : LinkedHashMap<String, Proc>,
+    /// The files listed under the `import` key, as they were written
+    pub imports : Vec<(String, Pos)>,
+    /// The variables listed under the `extern` key. The game sets
+    /// them, so the story may read them without setting them
+    pub externs : Vec<(String, Pos)>,
+}
+
+fn parse_yaml_code(src : Vec<MarkedYaml>) -> Result<Vec<Ast>, CompileError> {
+    src.into_iter().map(parse_yaml_command).collect()
+}
+
+// The option's name is the key of the code array. Besides that
+// the hash may contain a `when` condition and the `once` and
+// `fallback` flags
+fn parse_yaml_choice_branch(src : MarkedYaml) -> Result<ChoiceBranch, CompileError> {
+    let map = {
+        match src.node {
+            Node::Hash(map) if !map.is_empty() => map,
+            _ => return Err(CompileError::new(CompileErrorKind::BadChoiceBranch, src.pos)),
+        }
+    };
+    let first_pos = map[0].0.pos;
+    let (mut option, mut when, mut once, mut fallback) = (None, None, None, None);
+    for (key, value) in map.into_iter() {
+        match (key.node, value.node) {
+            (Node::String(name), Node::Array(code)) if option.is_none() => { option = Some((name, key.pos, parse_yaml_code(code)?)); },
+            (Node::String(name), node) if name.trim() == "when" && when.is_none() => {
+                when = Some(parse_yaml_expr(MarkedYaml { node, pos : value.pos })?);
+            },
+            (Node::String(name), Node::Boolean(x)) if name.trim() == "once" && once.is_none() => { once = Some(x); },
+            (Node::String(name), Node::Boolean(x)) if name.trim() == "fallback" && fallback.is_none() => { fallback = Some(x); },
+            (Node::String(_), _) if option.is_none() => return Err(CompileError::new(CompileErrorKind::BadChoiceOption, key.pos)),
+            _ => return Err(CompileError::new(CompileErrorKind::BadChoiceBranch, key.pos)),
+        }
+    }
+    match option {
+        Some((name, pos, code)) => 
+            Ok(ChoiceBranch { name, pos, code, when, once : once.unwrap_or(false), fallback : fallback.unwrap_or(false) }),
+        None => Err(CompileError::new(CompileErrorKind::BadChoiceBranch, first_pos)),
+    }
+}
+
+// Strings are parsed as expressions, while integers and booleans
+// are just literals
+fn parse_yaml_expr(src : MarkedYaml) -> Result<Expr, CompileError> {
+    let pos = src.pos;
+    match src.node {
+        Node::Integer(x) => Ok(Expr::Literal(Value::Int(x))),
+        Node::Boolean(x) => Ok(Expr::Literal(Value::Bool(x))),
+        Node::String(x) => {
+            parse_expr(&x).map_err(
+                |e| {
+                    // Only correct for the one-line expressions, which
+                    // are the most of them
+                    let pos = Pos { line : pos.line, col : pos.col + e.offset };
+                    CompileError::new(CompileErrorKind::BadExpr(e), pos)
+                }
+            )
+        },
+        _ => Err(CompileError::new(CompileErrorKind::BadValue, pos)),
+    }
+}
+
+// Plain text stays a plain message
+fn parse_yaml_print(msg : String, pos : Pos) -> Result<AstKind, CompileError> {
+    let mut parts = {
+        parse_template(&msg).map_err(
+            |e| {
+                let pos = Pos { line : pos.line, col : pos.col + e.offset };
+                CompileError::new(CompileErrorKind::BadExpr(e), pos)
+            }
+        )?
+    };
+    match parts.as_slice() {
+        [] => Ok(AstKind::Msg(String::new())),
+        [TemplatePart::Text(_)] => {
+            match parts.pop() {
+                Some(TemplatePart::Text(x)) => Ok(AstKind::Msg(x)),
+                _ => unreachable!(),
+            }
+        },
+        _ => Ok(AstKind::Template(parts, pos)),
+    }
+}
+
+fn parse_yaml_assignment((name, value) : (MarkedYaml, MarkedYaml)) -> Result<(String, Expr), CompileError> {
+    match name.node {
+        Node::String(x) => Ok((x, parse_yaml_expr(value)?)),
+        _ => Err(CompileError::new(CompileErrorKind::BadVariableName, name.pos)),
+    }
+}
+
+// `if`, `then` and an optional `else`, all in one hash
+fn parse_yaml_if(map : Vec<(MarkedYaml, MarkedYaml)>, pos : Pos) -> Result<AstKind, CompileError> {
+    let (mut cond, mut then_code, mut else_code) = (None, None, None);
+    for (key, value) in map.into_iter() {
+        let slot = {
+            match key.as_str().map(str::trim) {
+                Some("if") if cond.is_none() => { cond = Some(parse_yaml_expr(value)?); continue },
+                Some("then") => &mut then_code,
+                Some("else") => &mut else_code,
+                _ => return Err(CompileError::new(CompileErrorKind::BadIf, key.pos)),
+            }
+        };
+        match value.node {
+            Node::Array(code) if slot.is_none() => { *slot = Some(parse_yaml_code(code)?); },
+            _ => return Err(CompileError::new(CompileErrorKind::BadIf, key.pos)),
+        }
+    }
+    match (cond, then_code) {
+        (Some(cond), Some(then_code)) => Ok(AstKind::If(cond, then_code, else_code.unwrap_or_default())),
+        _ => Err(CompileError::new(CompileErrorKind::BadIf, pos)),
+    }
+}
+
+fn is_if(map : &[(MarkedYaml, MarkedYaml)]) -> bool {
+    map.iter().any(|(key, _)| key.as_str().map(str::trim) == Some("if"))
 }
 
 // heart of the parser
-fn parse_yaml_command(src : Yaml) -> Ast {
-    match src {
-        Yaml::String(x) if x.trim() == "wait" => Ast::Wait,
-        Yaml::Hash(mut map) if map.len() == 1 => {
-            match map.pop_back() {
-                Some((Yaml::String(cmd), Yaml::String(msg))) if cmd.trim() == "print" => Ast::Msg(msg),
-                Some((Yaml::String(cmd), Yaml::String(id))) if cmd.trim() == "call" => Ast::Call(id),
-                Some((Yaml::String(cmd), Yaml::Array(options))) if cmd.trim() == "choose" => {
-                    let branches =
-                    options.into_iter()
-                    .map(
-                        |x| {
-                            match x {
-                                Yaml::Hash(mut map) if map.len() == 1 => {
-                                    match map.pop_back() {
-                                        Some((Yaml::String(name), Yaml::Array(code))) => (name, code.into_iter().map(parse_yaml_command).collect()),
-                                        _ => panic!("In the choice map the key must be a string and the value must be an array"),
-                                    }
-                                },
-                                _ => panic!("the choice branch must be a hash with one key-value pair"),
+fn parse_yaml_command(src : MarkedYaml) -> Result<Ast, CompileError> {
+    let mut pos = src.pos;
+    let kind = {
+        match src.node {
+            Node::String(x) if x.trim() == "wait" => AstKind::Wait,
+            Node::Hash(map) if is_if(&map) => {
+                pos = map[0].0.pos;
+                parse_yaml_if(map, pos)?
+            },
+            Node::Hash(mut map) if map.len() == 1 => {
+                let (cmd, arg) = map.pop().unwrap();
+                // The hash's marker points past the key, so we
+                // report the command's name instead
+                pos = cmd.pos;
+                let arg_pos = arg.pos;
+                match (cmd.as_str().map(str::trim), arg.node) {
+                    (Some("print"), Node::String(msg)) => parse_yaml_print(msg, arg_pos)?,
+                    (Some("call"), Node::String(id)) => AstKind::Call(id),
+                    (Some("choose"), Node::Array(options)) => {
+                        let options : Vec<_> =
+                            options.into_iter()
+                            .map(parse_yaml_choice_branch)
+                            .collect::<Result<_, _>>()?
+                        ;
+                        // Only one option can be taken automatically
+                        if options.iter().filter(|x| x.fallback).count() > 1 {
+                            return Err(CompileError::new(CompileErrorKind::ManyFallbacks, pos))
+                        }
+                        AstKind::Choice(options)
+                    },
+                    (Some("set"), Node::Hash(assignments)) =>
+                        AstKind::Set(
+                            assignments.into_iter()
+                            .map(parse_yaml_assignment)
+                            .collect::<Result<_, _>>()?
+                        ),
+                    _ => return Err(CompileError::new(CompileErrorKind::BadCommand, pos)),
+                }
+            },
+            _ => return Err(CompileError::new(CompileErrorKind::BadCommand, pos)),
+        }
+    };
+    Ok(Ast { kind, pos })
+}
+
+/// Calls `f` for every command, including the ones inside choices and `if`s
+pub fn visit_code<'a>(code : &'a [Ast], f : &mut dyn FnMut(&'a Ast)) {
+    for ast in code.iter() {
+        f(ast);
+        match &ast.kind {
+            AstKind::Choice(options) => options.iter().for_each(|x| visit_code(&x.code, f)),
+            AstKind::If(_, then_code, else_code) => {
+                visit_code(then_code, f);
+                visit_code(else_code, f);
+            },
+            _ => (),
+        }
+    }
+}
+
+/// The variables which the `set`s of the file write or which are listed
+/// under `extern`, and the ones which its expressions and texts read. They
+/// may only read the variables which are declared somewhere in the story,
+/// which the linker checks. So a word which was meant to be a text, like
+/// `set: { name: Bob }`, isn't taken for a variable which is never set
+pub fn variable_usages(file : &File) -> (Vec<String>, Vec<(String, Pos)>) {
+    let mut declared = file.externs.iter().map(|(name, _)| name.clone()).collect::<Vec<_>>();
+    let mut reads = Vec::new();
+    for proc in file.procs.values() {
+        visit_code(
+            &proc.code, 
+            &mut |ast| {
+                let mut read = |expr : &Expr, pos : Pos| reads.extend(expr.variables().into_iter().map(|x| (x.to_string(), pos)));
+                match &ast.kind {
+                    AstKind::Set(assignments) => {
+                        for (name, expr) in assignments.iter() {
+                            declared.push(name.clone());
+                            read(expr, ast.pos);
+                        }
+                    },
+                    AstKind::If(condition, _, _) => read(condition, ast.pos),
+                    AstKind::Choice(options) => {
+                        for option in options.iter() {
+                            if let Some(when) = &option.when { read(when, option.pos); }
+                        }
+                    },
+                    AstKind::Template(parts, pos) => {
+                        for part in parts.iter() {
+                            if let TemplatePart::Expr(expr, offset) = part {
+                                read(expr, Pos { line : pos.line, col : pos.col + offset });
                             }
                         }
-                    ).collect();
-                    Ast::Choice(branches)
-                },
-                _ => panic!("The command doesn't satisfy an possible format"),
+                    },
+                    _ => (),
+                }
             }
+        );
+    }
+    declared.sort();
+    declared.dedup();
+    (declared, reads)
+}
+
+// `import`, `pub` and `extern` take either one name or an array of them.
+// The error is the position of the thing which isn't a string
+fn parse_yaml_names(src : MarkedYaml) -> Result<Vec<(String, Pos)>, Pos> {
+    match src.node {
+        Node::String(x) => Ok(vec![(x, src.pos)]),
+        Node::Array(xs) => {
+            xs.into_iter()
+            .map(
+                |x| {
+                    match x.node {
+                        Node::String(s) => Ok((s, x.pos)),
+                        _ => Err(x.pos),
+                    }
+                }
+            )
+            .collect()
         },
-        _ => panic!("The command doesn't satisfy an possible format"),
+        _ => Err(src.pos),
     }
 }
 
-pub fn parse_yaml(yaml_ast : Yaml) -> File {
-    if let Yaml::Hash(map) = yaml_ast {
-        let procs =
-        map.into_iter()
-        .map(
-            |(name, code)| {
-                let name = {
-                    if let Yaml::String(x) = name { x }
-                    else { panic!("The dialogue file must be keyed with strings") }
-                };
-                let code = {
-                    if let Yaml::Array(x) = code { x }
-                    else { panic!("The code of {} is not an array", name) }
-                };
-                (name, code.into_iter().map(&parse_yaml_command).collect())
+pub fn parse_yaml(yaml_ast : MarkedYaml) -> Result<File, CompileError> {
+    if let Node::Hash(map) = yaml_ast.node {
+        let mut procs = LinkedHashMap::new();
+        let mut imports = Vec::new();
+        let mut exports = Vec::new();
+        let mut externs = Vec::new();
+        for (name, code) in map.into_iter() {
+            // `import`, `pub` and `extern` are reserved keys, not dialogues
+            match name.as_str() {
+                Some("import") => {
+                    imports.extend(parse_yaml_names(code).map_err(|pos| CompileError::new(CompileErrorKind::BadImport, pos))?);
+                    continue;
+                },
+                Some("pub") => {
+                    exports.extend(parse_yaml_names(code).map_err(|pos| CompileError::new(CompileErrorKind::BadPub, pos))?);
+                    continue;
+                },
+                Some("extern") => {
+                    externs.extend(parse_yaml_names(code).map_err(|pos| CompileError::new(CompileErrorKind::BadExtern, pos))?);
+                    continue;
+                },
+                _ => (),
+            }
+            let pos = name.pos;
+            let name = {
+                match name.node {
+                    // `::` separates the module from the dialogue
+                    Node::String(x) if x.contains("::") => return Err(CompileError::new(CompileErrorKind::QualifiedProcName(x), name.pos)),
+                    Node::String(x) if !procs.contains_key(&x) => x,
+                    Node::String(x) => return Err(CompileError::new(CompileErrorKind::DuplicateProc(x), name.pos)),
+                    _ => return Err(CompileError::new(CompileErrorKind::ProcNameNotString, name.pos)),
+                }
+            };
+            let code = {
+                if let Node::Array(x) = code.node { x }
+                else { return Err(CompileError::new(CompileErrorKind::ProcNotArray(name), code.pos)) }
+            };
+            let code = parse_yaml_code(code)?;
+            let public = name == "main";
+            procs.insert(name, Proc { pos, code, public });
+        }
+        for (name, pos) in exports.into_iter() {
+            match procs.get_mut(&name) {
+                Some(proc) => proc.public = true,
+                None => return Err(CompileError::new(CompileErrorKind::UnknownDialogue(name), pos)),
             }
-        ).collect();
-        File { procs }
-    } else { panic!("The file's root must be a hashmap") }
+        }
+        Ok(File { procs, imports, externs })
+    } else { Err(CompileError::new(CompileErrorKind::RootNotHash, yaml_ast.pos)) }
 }
@@ -0,0 +1,119 @@
+use crate::vm::{ ProgramState, Snapshot };
+use crate::error::LoadError;
+use crate::opcode_saver::value_into_yaml;
+use crate::opcode_loader::parse_yaml_value;
+
+use yaml_rust::yaml::Yaml;
+
+fn state_name(state : ProgramState) -> &'static str {
+    match state {
+        ProgramState::Waiting => "waiting",
+        ProgramState::WaitingForChoice => "waiting_for_choice",
+        ProgramState::Paused => "paused",
+        ProgramState::Terminated => "terminated",
+    }
+}
+
+fn parse_state_name(name : &str) -> Option<ProgramState> {
+    match name {
+        "waiting" => Some(ProgramState::Waiting),
+        "waiting_for_choice" => Some(ProgramState::WaitingForChoice),
+        "paused" => Some(ProgramState::Paused),
+        "terminated" => Some(ProgramState::Terminated),
+        _ => None,
+    }
+}
+
+pub fn snapshot_into_yaml(snapshot : &Snapshot) -> Yaml {
+    Yaml::Hash(
+        vec![
+            // Written in hex, because YAML integers are signed
+            (Yaml::String("fingerprint".to_string()), Yaml::String(format!("{:016x}", snapshot.fingerprint))),
+            (Yaml::String("instruction_ptr".to_string()), Yaml::Integer(snapshot.instruction_ptr as i64)),
+            (
+                Yaml::String("frame_stack".to_string()),
+                Yaml::Array(snapshot.frame_stack.iter().map(|x| Yaml::Integer(*x as i64)).collect())
+            ),
+            (
+                Yaml::String("value_stack".to_string()),
+                Yaml::Array(snapshot.value_stack.iter().map(value_into_yaml).collect())
+            ),
+            (
+                Yaml::String("variables".to_string()),
+                Yaml::Hash(
+                    snapshot.variables.iter()
+                    .map(|(k, v)| (Yaml::String(k.clone()), value_into_yaml(v)))
+                    .collect()
+                )
+            ),
+            (
+                Yaml::String("visible_options".to_string()),
+                Yaml::Array(snapshot.visible_options.iter().map(|x| Yaml::Integer(*x as i64)).collect())
+            ),
+            (
+                Yaml::String("taken_options".to_string()),
+                Yaml::Array(
+                    snapshot.taken_options.iter()
+                    .map(|(branch, leaf)| Yaml::Array(vec![Yaml::Integer(*branch as i64), Yaml::Integer(*leaf as i64)]))
+                    .collect()
+                )
+            ),
+            (Yaml::String("state".to_string()), Yaml::String(state_name(snapshot.state).to_string())),
+        ]
+        .into_iter().collect()
+    )
+}
+
+fn parse_address(yaml : &Yaml) -> Option<usize> {
+    match yaml {
+        Yaml::Integer(x) if *x >= 0 => Some(*x as usize),
+        _ => None,
+    }
+}
+
+pub fn parse_yaml_snapshot(yaml : Yaml) -> Result<Snapshot, LoadError> {
+    let parse = || {
+        let fingerprint = u64::from_str_radix(yaml["fingerprint"].as_str()?, 16).ok()?;
+        let instruction_ptr = parse_address(&yaml["instruction_ptr"])?;
+        let frame_stack =
+            yaml["frame_stack"].as_vec()?
+            .iter()
+            .map(parse_address)
+            .collect::<Option<_>>()?
+        ;
+        let value_stack =
+            yaml["value_stack"].as_vec()?
+            .iter()
+            .map(|x| parse_yaml_value(x.clone()))
+            .collect::<Option<_>>()?
+        ;
+        let variables =
+            yaml["variables"].as_hash()?
+            .iter()
+            .map(|(k, v)| Some((k.as_str()?.to_string(), parse_yaml_value(v.clone())?)))
+            .collect::<Option<_>>()?
+        ;
+        let visible_options =
+            yaml["visible_options"].as_vec()?
+            .iter()
+            .map(parse_address)
+            .collect::<Option<_>>()?
+        ;
+        let taken_options =
+            yaml["taken_options"].as_vec()?
+            .iter()
+            .map(
+                |x| {
+                    match x.as_vec()?.as_slice() {
+                        [branch, leaf] => Some((parse_address(branch)?, parse_address(leaf)?)),
+                        _ => None,
+                    }
+                }
+            )
+            .collect::<Option<_>>()?
+        ;
+        let state = parse_state_name(yaml["state"].as_str()?)?;
+        Some(Snapshot { fingerprint, instruction_ptr, frame_stack, value_stack, variables, visible_options, taken_options, state })
+    };
+    parse().ok_or(LoadError::BadSnapshot)
+}
@@ -1,6 +1,10 @@
-// TODO: tail call optimization
+use crate::parser::{ Ast, AstKind, ChoiceBranch, File, variable_usages };
+use crate::expr::{ Expr, TemplatePart };
+use crate::error::CompileError;
+use crate::marked_yaml::Pos;
+use crate::vm::{ BinOp, UnOp, TemplatePiece, Value };
 
-use crate::parser::{ Ast, File };
+use std::path::{ Path, PathBuf };
 
 use log::debug;
 use linked_hash_map::LinkedHashMap;
@@ -8,39 +12,181 @@ use linked_hash_map::LinkedHashMap;
 pub struct BranchPreLeaf {
     pub option_name : String,
     pub jmp_address : usize,
+    pub conditional : bool,
+    pub once : bool,
+    pub fallback : bool,
 }
 
 pub enum PreInstruction {
     Ret,                            // a `restore` instruction. Jumps to the location pointed by the top
     Msg(String),                    // asks the host to write a message
+    Template(Vec<TemplatePiece>),   // pop the values and ask the host to write them together with the text
     Jmp(usize),                    
+    JmpIfNot(usize),                // pop a boolean and jump if it's false
     Wait,                           // asks the host to "flush" the messages (show them to the user) with "press X to continue"
-    Branch(Vec<BranchPreLeaf>),     // offer the user to choose the branch. The vm then simple-jumps to the location
+    Branch(Vec<BranchPreLeaf>),     // pop the conditions and offer the user to choose the branch. The vm then simple-jumps to the location
     PushPtr(usize),                 // put a pointer on the stack
-    UnresolvedCall(String),
+    Push(Value),                    // put a value on the value stack
+    Load(String),                   // push the variable's value
+    Store(String),                  // pop a value and write it into the variable
+    BinOp(BinOp),                   // pop two values and push the result
+    UnOp(UnOp),                     // pop a value and push the result
+    UnresolvedCall(String, Pos),     // a call to the procedure, which is resolved by the linker
 }
 
+/// A translated dialogue. The addresses inside of it
+/// are relative to its start
+pub struct Object {
+    pub pos : Pos,
+    pub code : Vec<PreInstruction>,
+    pub public : bool,
+}
+
+/// The translated dialogues of one module, which `link_objects`
+/// puts together. `save_object` and `load_object` store them
 pub struct ObjectFiles {
-    pub objects : LinkedHashMap<String, Vec<PreInstruction>>,
+    /// The dialogue file the objects were made from.
+    /// Empty if the source isn't a file
+    pub(crate) source : PathBuf,
+    pub(crate) module : String,
+    /// The variables which the `set`s write and the `extern`s
+    pub(crate) variables : Vec<String>,
+    /// The variables which the expressions and the texts read,
+    /// checked by the linker
+    pub(crate) reads : Vec<(String, Pos)>,
+    pub(crate) objects : LinkedHashMap<String, Object>,
+}
+
+/// The full name of the dialogue which `call: name` in the module refers
+/// to. A plain name refers to the dialogue of the same module
+pub fn call_target(module : &str, name : &str) -> String {
+    if name.contains("::") { name.to_string() }
+    else { qualified_name(module, name) }
+}
+
+/// The module is named after the file without the extension
+pub fn module_name(source : &Path) -> String {
+    source.file_stem().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+/// The full name of the dialogue: `module::name`. `main` is the
+/// story's starting point, so it's the same in every module. The
+/// module without a name doesn't add anything either
+pub fn qualified_name(module : &str, name : &str) -> String {
+    if module.is_empty() || name == "main" { name.to_string() }
+    else { format!("{}::{}", module, name) }
 }
 
-fn translate_ast_impl(ast : Ast, pre_opcodes : &mut Vec<PreInstruction>) {
-    match ast {
-        Ast::Msg(x) => pre_opcodes.push(PreInstruction::Msg(x)),
-        Ast::Choice(choice_arr) => {
+fn translate_expr(expr : Expr, pre_opcodes : &mut Vec<PreInstruction>) {
+    match expr {
+        Expr::Literal(x) => pre_opcodes.push(PreInstruction::Push(x)),
+        Expr::Var(x) => pre_opcodes.push(PreInstruction::Load(x)),
+        Expr::Unary(op, x) => {
+            translate_expr(*x, pre_opcodes);
+            pre_opcodes.push(PreInstruction::UnOp(op));
+        },
+        Expr::Binary(op, lhs, rhs) => {
+            translate_expr(*lhs, pre_opcodes);
+            translate_expr(*rhs, pre_opcodes);
+            pre_opcodes.push(PreInstruction::BinOp(op));
+        },
+        /*
+                <lhs>
+                jmp_if_not  short
+                <rhs>
+                jmp         end
+            short:
+                push        false
+            end:
+        */
+        Expr::And(lhs, rhs) => {
+            translate_expr(*lhs, pre_opcodes);
+            let short_jmp_place = pre_opcodes.len();
+            pre_opcodes.push(PreInstruction::Ret); // Some dummy value which we'll update later
+            translate_expr(*rhs, pre_opcodes);
+            pre_opcodes.push(PreInstruction::Jmp(pre_opcodes.len() + 2));
+            pre_opcodes[short_jmp_place] = PreInstruction::JmpIfNot(pre_opcodes.len());
+            pre_opcodes.push(PreInstruction::Push(Value::Bool(false)));
+        },
+        /*
+                <lhs>
+                jmp_if_not  long
+                push        true
+                jmp         end
+            long:
+                <rhs>
+            end:
+        */
+        Expr::Or(lhs, rhs) => {
+            translate_expr(*lhs, pre_opcodes);
+            let long_jmp_place = pre_opcodes.len();
+            pre_opcodes.push(PreInstruction::Ret); // Some dummy value which we'll update later
+            pre_opcodes.push(PreInstruction::Push(Value::Bool(true)));
+            let end_jmp_place = pre_opcodes.len();
+            pre_opcodes.push(PreInstruction::Ret);
+            pre_opcodes[long_jmp_place] = PreInstruction::JmpIfNot(pre_opcodes.len());
+            translate_expr(*rhs, pre_opcodes);
+            pre_opcodes[end_jmp_place] = PreInstruction::Jmp(pre_opcodes.len());
+        },
+    }
+}
+
+// The code is in the tail position if nothing but `ret` runs after it.
+// Then its last command is in the tail position too
+fn translate_code(code : Vec<Ast>, tail : bool, pre_opcodes : &mut Vec<PreInstruction>) {
+    let last = code.len().saturating_sub(1);
+    for (i, x) in code.into_iter().enumerate() {
+        translate_ast_impl(x, tail && i == last, pre_opcodes);
+    }
+}
+
+fn translate_ast_impl(ast : Ast, tail : bool, pre_opcodes : &mut Vec<PreInstruction>) {
+    match ast.kind {
+        AstKind::Msg(x) => pre_opcodes.push(PreInstruction::Msg(x)),
+        AstKind::Template(parts, _) => {
+            // The values are computed in the order of the text
+            let pieces =
+                parts.into_iter()
+                .map(
+                    |x| {
+                        match x {
+                            TemplatePart::Text(x) => TemplatePiece::Text(x),
+                            TemplatePart::Expr(x, _) => { translate_expr(x, pre_opcodes); TemplatePiece::Value },
+                        }
+                    }
+                )
+                .collect()
+            ;
+            pre_opcodes.push(PreInstruction::Template(pieces));
+        },
+        AstKind::Choice(choice_arr) => {
+            // The conditions are computed before the branch. The one of
+            // the last conditional option ends up on the top of the stack
+            let choice_arr : Vec<_> =
+                choice_arr.into_iter()
+                .map(
+                    |ChoiceBranch { name, code, when, once, fallback, .. }| {
+                        let conditional = when.is_some();
+                        if let Some(cond) = when { translate_expr(cond, pre_opcodes); }
+                        (name, code, conditional, once, fallback)
+                    }
+                )
+                .collect()
+            ;
             let choice_instr_place = pre_opcodes.len(); // remember the location where to put the jump instruction
             pre_opcodes.push(PreInstruction::Ret); // Some dummy value which we'll update later
 
             let (leaves, place_holders) : (Vec<_>, Vec<_>) =  
                 choice_arr.into_iter()
                 .map(
-                    |(option_name, code)| {
+                    |(option_name, code, conditional, once, fallback)| {
                         let jmp_address = pre_opcodes.len();
-                        code.into_iter().for_each(|x| translate_ast_impl(x, pre_opcodes));
+                        // after the option the choice is over, so the option's tail is the choice's
+                        translate_code(code, tail, pre_opcodes);
                         let aftermath_address = pre_opcodes.len();
                         pre_opcodes.push(PreInstruction::Ret);
                         (
-                            BranchPreLeaf { option_name, jmp_address },
+                            BranchPreLeaf { option_name, jmp_address, conditional, once, fallback },
                             aftermath_address
                         )
                     }
@@ -51,8 +197,34 @@ fn translate_ast_impl(ast : Ast, pre_opcodes : &mut Vec<PreInstruction>) {
             let after_choice = pre_opcodes.len();
             place_holders.into_iter().for_each(|x| pre_opcodes[x] = PreInstruction::Jmp(after_choice));
         },
-        Ast::Wait => pre_opcodes.push(PreInstruction::Wait),
-        Ast::Call(x) => {
+        AstKind::Wait => pre_opcodes.push(PreInstruction::Wait),
+        AstKind::Set(assignments) => {
+            for (name, value) in assignments.into_iter() {
+                translate_expr(value, pre_opcodes);
+                pre_opcodes.push(PreInstruction::Store(name));
+            }
+        },
+        AstKind::If(cond, then_code, else_code) => {
+            translate_expr(cond, pre_opcodes);
+            let cond_jmp_place = pre_opcodes.len();
+            pre_opcodes.push(PreInstruction::Ret); // Some dummy value which we'll update later
+            translate_code(then_code, tail, pre_opcodes);
+            if else_code.is_empty() {
+                pre_opcodes[cond_jmp_place] = PreInstruction::JmpIfNot(pre_opcodes.len());
+            } else {
+                // the `then` branch must jump over the `else` one
+                let then_end_place = pre_opcodes.len();
+                pre_opcodes.push(PreInstruction::Ret);
+                pre_opcodes[cond_jmp_place] = PreInstruction::JmpIfNot(pre_opcodes.len());
+                translate_code(else_code, tail, pre_opcodes);
+                pre_opcodes[then_end_place] = PreInstruction::Jmp(pre_opcodes.len());
+            }
+        },
+        // The dialogue would return right to our `ret`, so it may as well
+        // return to where we'd return. That's just a jump, which keeps the
+        // frame stack small for the stories which loop through `call`s
+        AstKind::Call(x) if tail => pre_opcodes.push(PreInstruction::UnresolvedCall(x, ast.pos)),
+        AstKind::Call(x) => {
             /*
                 pre_opcodes.len()       points at `push_ptr`
                 pre_opcodes.len() + 1   points at the `call`
@@ -60,30 +232,33 @@ fn translate_ast_impl(ast : Ast, pre_opcodes : &mut Vec<PreInstruction>) {
             */
             let after_call = pre_opcodes.len() + 2;
             pre_opcodes.push(PreInstruction::PushPtr(after_call));
-            pre_opcodes.push(PreInstruction::UnresolvedCall(x));
+            pre_opcodes.push(PreInstruction::UnresolvedCall(x, ast.pos));
         },
     }
 }
 
 pub fn translate_ast(ast : Vec<Ast>) -> Vec<PreInstruction> {
     let mut pre_opcodes = Vec::new();
-    for x in ast.into_iter() {
-        translate_ast_impl(x, &mut pre_opcodes);
-    }
+    translate_code(ast, true, &mut pre_opcodes);
     pre_opcodes.push(PreInstruction::Ret);
     debug!(target: "translator", "Done translating. {} pre opcodes processed", pre_opcodes.len());
     pre_opcodes
 }
 
-pub fn translate_file(file : File) -> ObjectFiles {
-    ObjectFiles {
-        objects : file.procs.into_iter()
+/// Translates the dialogues of the file, which becomes the module
+/// named after the source. See `module_name`
+pub fn translate_file(file : File, source : &Path) -> Result<ObjectFiles, CompileError> {
+    let module = module_name(source);
+    let (variables, reads) = variable_usages(&file);
+    let objects =
+        file.procs.into_iter()
         .map(
             |(k, v)| {
                 debug!(target: "translator", "Translating \"{}\"...", k);
-                (k, translate_ast(v))
+                (qualified_name(&module, &k), Object { pos : v.pos, code : translate_ast(v.code), public : v.public })
             }
         )
-        .collect(),
-    }
+        .collect()
+    ;
+    Ok(ObjectFiles { source : source.to_path_buf(), module, variables, reads, objects })
 }
@@ -1,13 +1,136 @@
-#[derive(Debug)]
+use crate::bytecode_saver::opcodes_into_bytes;
+
+use std::fmt;
+use std::sync::Arc;
+use std::error::Error;
+use std::collections::{ BTreeMap, BTreeSet };
+
+/// A value of a story variable
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    Str(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(x) => write!(f, "{}", x),
+            Value::Bool(x) => write!(f, "{}", x),
+            Value::Str(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+/// An operator which takes two values from the value stack
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl BinOp {
+    /// How the operator is written in the dialogue files
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Rem => "%",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+        }
+    }
+
+    pub fn is_comparison(self) -> bool {
+        matches!(self, BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge)
+    }
+
+    // `None` means the operator doesn't accept such values.
+    // The caller must take care of the division by zero.
+    fn apply(self, lhs : Value, rhs : Value) -> Option<Value> {
+        match (self, lhs, rhs) {
+            (BinOp::Eq, a, b) if std::mem::discriminant(&a) == std::mem::discriminant(&b) => Some(Value::Bool(a == b)),
+            (BinOp::Ne, a, b) if std::mem::discriminant(&a) == std::mem::discriminant(&b) => Some(Value::Bool(a != b)),
+            (BinOp::Add, Value::Str(a), Value::Str(b)) => Some(Value::Str(a + &b)),
+            (op, Value::Int(a), Value::Int(b)) => {
+                match op {
+                    BinOp::Add => Some(Value::Int(a.wrapping_add(b))),
+                    BinOp::Sub => Some(Value::Int(a.wrapping_sub(b))),
+                    BinOp::Mul => Some(Value::Int(a.wrapping_mul(b))),
+                    BinOp::Div => Some(Value::Int(a.wrapping_div(b))),
+                    BinOp::Rem => Some(Value::Int(a.wrapping_rem(b))),
+                    BinOp::Lt => Some(Value::Bool(a < b)),
+                    BinOp::Le => Some(Value::Bool(a <= b)),
+                    BinOp::Gt => Some(Value::Bool(a > b)),
+                    BinOp::Ge => Some(Value::Bool(a >= b)),
+                    BinOp::Eq | BinOp::Ne => unreachable!(),
+                }
+            },
+            _ => None,
+        }
+    }
+}
+
+/// An operator which takes one value from the value stack
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UnOp {
+    Not,
+    Neg,
+}
+
+impl UnOp {
+    fn apply(self, x : Value) -> Option<Value> {
+        match (self, x) {
+            (UnOp::Not, Value::Bool(x)) => Some(Value::Bool(!x)),
+            (UnOp::Neg, Value::Int(x)) => Some(Value::Int(x.wrapping_neg())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct BranchLeaf {
     /// The string which will be seen by the user
     /// when they are asked to pick an option
     pub option_name : String,
     /// The address where VM will jump
     pub jmp_address : usize,
+    /// The option is only offered if its condition,
+    /// taken from the value stack, is true
+    pub conditional : bool,
+    /// The option disappears after it's picked
+    pub once : bool,
+    /// The option isn't offered. Instead it's taken automatically
+    /// when no other option is left
+    pub fallback : bool,
 }
 
-#[derive(Debug)]
+/// A piece of a message which is rendered at runtime
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TemplatePiece {
+    /// The text as it is
+    Text(String),
+    /// A value taken from the value stack
+    Value,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Instruction {
     /// This a basic return. It either jump to
     /// the location pointed by the top of the
@@ -17,10 +140,19 @@ pub enum Instruction {
     /// A simple unconditional jump
     Jmp(usize),
 
+    /// Takes a boolean from the value stack and
+    /// jumps if it's false
+    JmpIfNot(usize),
+
     /// This instruction makes the vm ask the
     /// client to print a message
     Msg(String),
 
+    /// Like `Msg`, but the message is glued from the text and
+    /// the values taken from the value stack. The value of the
+    /// last piece is on the top.
+    Template(Vec<TemplatePiece>),
+
     /// This instruction makes the vm as the
     /// client to "pause"
     Wait,
@@ -28,12 +160,33 @@ pub enum Instruction {
     /// This is a simple branching. It offers
     /// the client to pic an option. When they
     /// pick the option, the vm jumps to the
-    /// corresponding piece of code (see the `BranchLeaf` struct)
+    /// corresponding piece of code (see the `BranchLeaf` struct).
+    /// Takes a boolean from the value stack for every conditional
+    /// option. The last option's condition is on the top.
     Branch(Vec<BranchLeaf>),
 
     /// This instruction puts a point on the
     /// frame stuck
     PushPtr(usize),
+
+    /// Puts a value on the value stack
+    Push(Value),
+
+    /// Puts the variable's value on the value stack
+    Load(String),
+
+    /// Takes two values from the value stack and puts
+    /// the result of the operator back. The right operand
+    /// is on the top.
+    BinOp(BinOp),
+
+    /// Takes a value from the value stack and puts the
+    /// result of the operator back
+    UnOp(UnOp),
+
+    /// Takes a value from the value stack and
+    /// writes it into the variable
+    Store(String),
 }
 
 /// A `Program` is what our VM runs. To run a program an entry point
@@ -41,42 +194,196 @@ pub enum Instruction {
 pub struct Program {
     entry_point : usize,
     opcodes : Vec<Instruction>,
+    fingerprint : u64,
+    // the names of the dialogues keyed with their addresses
+    names : BTreeMap<usize, String>,
+}
+
+/// How deep the calls may go by default. The tail calls don't count,
+/// so only a runaway recursion gets that far
+pub const DEFAULT_MAX_DEPTH : usize = 1024;
+
+// FNV-1a over the opcodes written as bytecode. The bytecode spells out
+// every byte, so unlike the derived `Hash` the fingerprint doesn't depend
+// on the platform or the Rust release, and the saves stay valid
+fn fingerprint(opcodes : &[Instruction]) -> u64 {
+    opcodes_into_bytes(opcodes).iter()
+    .fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
 }
 
 impl Program {
     /// The constructor
     pub fn new(opcodes : Vec<Instruction>, entry_point : usize) -> Program {
         Program {
+            fingerprint : fingerprint(&opcodes),
             opcodes,
             entry_point,
+            names : BTreeMap::new(),
+        }
+    }
+
+    /// Names the dialogues starting at the addresses, e.g. with the
+    /// executable's symbol table. The names are only used in the errors
+    pub fn with_names<I : IntoIterator<Item = (String, usize)>>(mut self, names : I) -> Program {
+        self.names = names.into_iter().map(|(name, address)| (address, name)).collect();
+        self
+    }
+
+    // The address as `dialogue+offset`. The dialogues are laid out one
+    // after another, so it's the one starting last before the address.
+    // That only holds if every dialogue is named, so the addresses
+    // past the end stay plain
+    fn dialogue_at(&self, address : usize) -> String {
+        if address >= self.opcodes.len() { return address.to_string() }
+        match self.names.range(..=address).next_back() {
+            Some((start, name)) if *start == address => name.clone(),
+            Some((start, name)) => format!("{}+{}", name, address - start),
+            None => address.to_string(),
         }
     }
 
-    /// Create a VM instance
-    pub fn run(&self) -> ProgramExecutor {
+    /// The opcodes the program consists of
+    pub fn opcodes(&self) -> &[Instruction] {
+        &self.opcodes
+    }
+
+    // Where the VM may start from: the entry point and the named dialogues
+    pub(crate) fn starts(&self) -> impl Iterator<Item = usize> + '_ {
+        Some(self.entry_point).into_iter().chain(self.names.keys().copied())
+    }
+
+    /// Create a VM instance. The program is shared, so
+    /// any amount of sessions can run it at once
+    pub fn run(self : &Arc<Self>) -> ProgramExecutor {
         ProgramExecutor {
-            my_program : self,
+            my_program : Arc::clone(self),
             instruction_ptr : self.entry_point,
             frame_stack : Vec::new(),
+            value_stack : Vec::new(),
+            variables : BTreeMap::new(),
+            visible_options : Vec::new(),
+            taken_options : BTreeSet::new(),
             state : ProgramState::Paused,
+            max_depth : Some(DEFAULT_MAX_DEPTH),
+        }
+    }
+
+    /// Create a VM instance which continues the execution
+    /// from the snapshot. The snapshot must be taken from
+    /// the same program.
+    pub fn resume(self : &Arc<Self>, snapshot : Snapshot) -> Result<ProgramExecutor, VmError> {
+        if snapshot.fingerprint != self.fingerprint {
+            return Err(VmError::FingerprintMismatch { expected : self.fingerprint, actual : snapshot.fingerprint });
+        }
+        // Make sure the snapshot respects the specification
+        // of `ProgramState`
+        if snapshot.state == ProgramState::WaitingForChoice {
+            match self.opcodes.get(snapshot.instruction_ptr) {
+                Some(Instruction::Branch(data)) 
+                    if !snapshot.visible_options.is_empty() && 
+                    snapshot.visible_options.iter().all(|x| *x < data.len()) => (),
+                _ => return Err(VmError::InvalidSnapshot),
+            }
         }
+        Ok(
+            ProgramExecutor {
+                my_program : Arc::clone(self),
+                instruction_ptr : snapshot.instruction_ptr,
+                frame_stack : snapshot.frame_stack,
+                value_stack : snapshot.value_stack,
+                variables : snapshot.variables,
+                visible_options : snapshot.visible_options,
+                taken_options : snapshot.taken_options,
+                state : snapshot.state,
+                max_depth : Some(DEFAULT_MAX_DEPTH),
+            }
+        )
     }
 }
 
 // Specification
 //  IF state = WaitingForChoice then the vm's
 //      instruction ptr is points at a `Branch` instruction
+//      and `visible_options` are non-empty valid indices of its leaves
 //  ELSE nothing
-#[derive(Clone, Copy, PartialEq, Eq)]
-enum ProgramState {
+/// The state of the VM. It determines which signal the VM expects
+/// from the client.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ProgramState {
+    /// Waiting for `done_printing`
     Waiting,
+    /// Waiting for `choose`
     WaitingForChoice,
+    /// Waiting for `unpause`
     Paused,
+    /// The execution has ended. The VM doesn't expect anything
     Terminated,
 }
 
+/// An error the VM reports instead of crashing. When an error
+/// is returned, the VM stays in a consistent state: the client's
+/// misuse doesn't change anything, while a faulty instruction leaves
+/// the VM paused with the instruction pointer on that instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VmError {
+    /// The client sent a signal the VM wasn't waiting for
+    WrongState { expected : ProgramState, actual : ProgramState },
+    /// The client picked an option which doesn't exist.
+    /// Carries the picked id and the amount of options.
+    InvalidChoice(usize, usize),
+    /// The instruction pointer fell out of the opcode array
+    IpOutOfRange(usize),
+    /// The instruction at the address jumps to itself
+    SelfJump(usize),
+    /// The instruction at the address needs a value, but
+    /// the value stack is empty
+    StackUnderflow(usize),
+    /// The instruction at the address got a value of a wrong type
+    TypeMismatch(usize),
+    /// The story reads a variable nobody has written
+    UndefinedVariable(String),
+    /// The instruction at the address divides by zero
+    DivisionByZero(usize),
+    /// The branch at the address has no options to offer,
+    /// because all of them are hidden or used up
+    NoVisibleOptions(usize),
+    /// The snapshot was taken from a different program
+    FingerprintMismatch { expected : u64, actual : u64 },
+    /// The snapshot contradicts the program
+    InvalidSnapshot,
+    /// The call at the address would go deeper than the executor allows.
+    /// Carries the dialogues on the call stack, the outermost first
+    StackOverflow { ip : usize, chain : Vec<String> },
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::WrongState { expected, actual } => write!(f, "the VM was expected to be {:?}, but it is {:?}", expected, actual),
+            VmError::InvalidChoice(id, count) => write!(f, "option {} is out of range (there are {} options)", id, count),
+            VmError::IpOutOfRange(ip) => write!(f, "instruction ptr out of range ({})", ip),
+            VmError::SelfJump(ip) => write!(f, "the instruction at {} jumps to itself", ip),
+            VmError::StackUnderflow(ip) => write!(f, "the instruction at {} needs a value, but the value stack is empty", ip),
+            VmError::TypeMismatch(ip) => write!(f, "the instruction at {} got a value of a wrong type", ip),
+            VmError::UndefinedVariable(name) => write!(f, "the variable \"{}\" is not defined", name),
+            VmError::DivisionByZero(ip) => write!(f, "the instruction at {} divides by zero", ip),
+            VmError::NoVisibleOptions(ip) => write!(f, "the branch at {} has no options to offer", ip),
+            VmError::FingerprintMismatch { expected, actual } => write!(f, "the snapshot was taken from another program (expected fingerprint {:016x}, got {:016x})", expected, actual),
+            VmError::InvalidSnapshot => write!(f, "the snapshot doesn't fit the program"),
+            VmError::StackOverflow { ip, chain } => {
+                // a runaway recursion makes a long chain, the end tells enough
+                let shown = chain.len().saturating_sub(8);
+                let prefix = if shown > 0 { "... -> " } else { "" };
+                write!(f, "the call at {} goes {} dialogues deep: {}{}", ip, chain.len(), prefix, chain[shown..].join(" -> "))
+            },
+        }
+    }
+}
+
+impl Error for VmError {}
+
 /// A request is what the VM wants the client to do
-pub enum Request<'a> {
+pub enum Request {
     /// The client must shutdown all the systems
     /// which are waiting for commands from the VM.
     /// The sessions has ended.
@@ -89,7 +396,7 @@ pub enum Request<'a> {
     Resume,
 
     /// The client must print a message.
-    PrintMessage(&'a str),
+    PrintMessage(String),
 
     /// The client must "pause". This is a different concept:
     /// In terms of CLI
@@ -102,41 +409,76 @@ pub enum Request<'a> {
 
     /// The VM has encouterd a branch and needs
     /// the client to pick an option. It will be waiting
-    /// for a signal with a branch id.
-    PerformChoice(&'a [BranchLeaf]),
+    /// for a signal with a branch id. Only the names of the options
+    /// which are offered are listed, and the id is the index in this list.
+    PerformChoice(Vec<String>),
+}
+
+/// Everything the VM needs to continue the execution later.
+/// Take it with `ProgramExecutor::snapshot` and continue with `Program::resume`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Snapshot {
+    /// The fingerprint of the program the snapshot was taken from
+    pub fingerprint : u64,
+    pub instruction_ptr : usize,
+    pub frame_stack : Vec<usize>,
+    pub value_stack : Vec<Value>,
+    pub variables : BTreeMap<String, Value>,
+    /// The options of the current branch which are offered to the client
+    pub visible_options : Vec<usize>,
+    /// The once-only options which were picked, as
+    /// the branch's address and the option's index
+    pub taken_options : BTreeSet<(usize, usize)>,
+    pub state : ProgramState,
 }
 
-/// The VM instance
-pub struct ProgramExecutor<'a> {
-    my_program : &'a Program,
+/// The VM instance. It keeps the program alive, so it can be
+/// stored anywhere and sent to another thread.
+pub struct ProgramExecutor {
+    my_program : Arc<Program>,
     instruction_ptr : usize,
     frame_stack : Vec<usize>,
+    // the stack the values are computed on
+    value_stack : Vec<Value>,
+    variables : BTreeMap<String, Value>,
+    // the indices of the leaves offered by the current branch
+    visible_options : Vec<usize>,
+    // (branch address, leaf index) of the once-only options which were picked
+    taken_options : BTreeSet<(usize, usize)>,
     state : ProgramState,
+    // how many frames the frame stack may hold
+    max_depth : Option<usize>,
 }
 
-impl<'a> ProgramExecutor<'a> {
+// A server keeps many sessions of one story. Make sure that stays possible
+const _ : fn() = || {
+    fn assert_send_sync<T : Send + Sync>() {}
+    assert_send_sync::<Program>();
+    assert_send_sync::<ProgramExecutor>();
+};
+
+impl ProgramExecutor {
     // The heart of our VM. The user will never see this
     // function
-    fn execute(&mut self, limit : Option<usize>) -> Request<'a> {
+    fn execute(&mut self, limit : Option<usize>) -> Result<Request, VmError> {
         // The limit of the opcodes is thse `usize` max if the user said
         // that there's no limit. :)
-        let mut limit = limit.unwrap_or(std::usize::MAX);
+        let mut limit = limit.unwrap_or(usize::MAX);
         // Let me note that I am creating an auxillary variable for the
         // instruction ptr... Why not?
         let mut instruction_ptr = self.instruction_ptr;
         let mut request = None;
-
-        // The user tried to wake us up when we did all the job!
-        if self.state == ProgramState::Terminated { panic!("Can't continue") }
+        let mut error = None;
 
         // the main loop.
         // Loop invariant:
         //  if the looping condition is true, then the VM is pointing
         //  on a new opcode or a new pointer was pushed on the frame stack
-        while limit > 0 && request.is_none() {
+        while limit > 0 && request.is_none() && error.is_none() {
             // setting up some aliases.
             let opcodes = &self.my_program.opcodes;
             let frame_stack = &mut self.frame_stack;
+            let value_stack = &mut self.value_stack;
 
             // fetching an opcode
             match opcodes.get(instruction_ptr) {
@@ -160,30 +502,170 @@ impl<'a> ProgramExecutor<'a> {
                             // But that lets me verify this VM on paper XD
                             // Why do you need an instruction that jumps to itself
                             // anyway?!
-                            if *x == instruction_ptr { panic!("Self jumps are not allowed"); }
-                            instruction_ptr = *x;
+                            if *x == instruction_ptr { error = Some(VmError::SelfJump(instruction_ptr)); }
+                            else { instruction_ptr = *x; }
                         },
                         Instruction::Msg(x) => {
-                            request = Some(Request::PrintMessage(x));
+                            request = Some(Request::PrintMessage(x.clone()));
                             instruction_ptr += 1;
                         },
+                        Instruction::Template(pieces) => {
+                            let value_count = pieces.iter().filter(|x| matches!(x, TemplatePiece::Value)).count();
+                            match value_stack.len().checked_sub(value_count) {
+                                Some(start) => {
+                                    let mut values = value_stack.drain(start..);
+                                    let msg : String =
+                                        pieces.iter()
+                                        .map(
+                                            |x| {
+                                                match x {
+                                                    TemplatePiece::Text(x) => x.clone(),
+                                                    TemplatePiece::Value => values.next().unwrap().to_string(),
+                                                }
+                                            }
+                                        )
+                                        .collect()
+                                    ;
+                                    request = Some(Request::PrintMessage(msg));
+                                    instruction_ptr += 1;
+                                },
+                                None => { error = Some(VmError::StackUnderflow(instruction_ptr)); },
+                            }
+                        },
                         Instruction::Wait => {
                             request = Some(Request::Wait);
                             instruction_ptr += 1;
                         },
                         Instruction::Branch(data) => {
-                            // Don't update the pointer. Keep it on the choice
-                            // instruction, follow the specification1
-                            request = Some(Request::PerformChoice(data));
+                            // Only look at the conditions first. If something is wrong,
+                            // the stack stays untouched
+                            let conditional_count = data.iter().filter(|x| x.conditional).count();
+                            let conditions = {
+                                match value_stack.len().checked_sub(conditional_count) {
+                                    Some(start) => {
+                                        value_stack[start..].iter()
+                                        .map(|x| if let Value::Bool(x) = x { Some(*x) } else { None })
+                                        .collect::<Option<Vec<_>>>()
+                                        .ok_or(VmError::TypeMismatch(instruction_ptr))
+                                    },
+                                    None => Err(VmError::StackUnderflow(instruction_ptr)),
+                                }
+                            };
+                            match conditions {
+                                Ok(conditions) => {
+                                    let mut conditions = conditions.into_iter();
+                                    let taken_options = &self.taken_options;
+                                    // The options whose conditions hold and which weren't used up
+                                    let available : Vec<usize> =
+                                        data.iter()
+                                        .enumerate()
+                                        .filter(|(_, x)| !x.conditional || conditions.next().unwrap())
+                                        .filter(|(i, x)| !x.once || !taken_options.contains(&(instruction_ptr, *i)))
+                                        .map(|(i, _)| i)
+                                        .collect()
+                                    ;
+                                    let visible_options : Vec<usize> = available.iter().copied().filter(|x| !data[*x].fallback).collect();
+                                    let fallback = available.iter().copied().find(|x| data[*x].fallback);
+                                    match (visible_options.is_empty(), fallback) {
+                                        (false, _) => {
+                                            value_stack.truncate(value_stack.len() - conditional_count);
+                                            // Don't update the pointer. Keep it on the choice
+                                            // instruction, follow the specification1
+                                            request = Some(Request::PerformChoice(visible_options.iter().map(|x| data[*x].option_name.clone()).collect()));
+                                            self.visible_options = visible_options;
+                                        },
+                                        // Nothing to offer. Take the fallback without asking
+                                        (true, Some(x)) => {
+                                            value_stack.truncate(value_stack.len() - conditional_count);
+                                            if data[x].once { self.taken_options.insert((instruction_ptr, x)); }
+                                            instruction_ptr = data[x].jmp_address;
+                                        },
+                                        (true, None) => { error = Some(VmError::NoVisibleOptions(instruction_ptr)); },
+                                    }
+                                },
+                                Err(e) => { error = Some(e); },
+                            }
+                        },
+                        Instruction::PushPtr(_) if self.max_depth.is_some_and(|x| frame_stack.len() >= x) => {
+                            // every frame points into its caller
+                            let program = &self.my_program;
+                            let chain = frame_stack.iter().chain(Some(&instruction_ptr)).map(|x| program.dialogue_at(*x)).collect();
+                            error = Some(VmError::StackOverflow { ip : instruction_ptr, chain });
                         },
                         Instruction::PushPtr(x) => {
                             frame_stack.push(*x);
                             instruction_ptr += 1;
                         },
+                        Instruction::Push(x) => {
+                            value_stack.push(x.clone());
+                            instruction_ptr += 1;
+                        },
+                        // Like with the branch, the operands are only taken once
+                        // they're fine. Otherwise the stack stays untouched
+                        Instruction::JmpIfNot(x) => {
+                            match value_stack.last() {
+                                Some(Value::Bool(cond)) => {
+                                    instruction_ptr = if *cond { instruction_ptr + 1 } else { *x };
+                                    value_stack.pop();
+                                },
+                                Some(_) => { error = Some(VmError::TypeMismatch(instruction_ptr)); },
+                                None => { error = Some(VmError::StackUnderflow(instruction_ptr)); },
+                            }
+                        },
+                        Instruction::Load(name) => {
+                            match self.variables.get(name) {
+                                Some(x) => {
+                                    value_stack.push(x.clone());
+                                    instruction_ptr += 1;
+                                },
+                                None => { error = Some(VmError::UndefinedVariable(name.clone())); },
+                            }
+                        },
+                        Instruction::BinOp(op) => {
+                            match value_stack.len().checked_sub(2) {
+                                Some(start) => {
+                                    let result = {
+                                        match (&value_stack[start], &value_stack[start + 1]) {
+                                            (Value::Int(_), Value::Int(0)) if matches!(op, BinOp::Div | BinOp::Rem) => Err(VmError::DivisionByZero(instruction_ptr)),
+                                            (lhs, rhs) => op.apply(lhs.clone(), rhs.clone()).ok_or(VmError::TypeMismatch(instruction_ptr)),
+                                        }
+                                    };
+                                    match result {
+                                        Ok(x) => {
+                                            value_stack.truncate(start);
+                                            value_stack.push(x);
+                                            instruction_ptr += 1;
+                                        },
+                                        Err(e) => { error = Some(e); },
+                                    }
+                                },
+                                None => { error = Some(VmError::StackUnderflow(instruction_ptr)); },
+                            }
+                        },
+                        Instruction::UnOp(op) => {
+                            match value_stack.last().map(|x| op.apply(x.clone())) {
+                                Some(Some(x)) => {
+                                    value_stack.pop();
+                                    value_stack.push(x);
+                                    instruction_ptr += 1;
+                                },
+                                Some(None) => { error = Some(VmError::TypeMismatch(instruction_ptr)); },
+                                None => { error = Some(VmError::StackUnderflow(instruction_ptr)); },
+                            }
+                        },
+                        Instruction::Store(name) => {
+                            match value_stack.pop() {
+                                Some(x) => {
+                                    self.variables.insert(name.clone(), x);
+                                    instruction_ptr += 1;
+                                },
+                                None => { error = Some(VmError::StackUnderflow(instruction_ptr)); },
+                            }
+                        },
                     }
                 },
                 // Fell out of the opcode array somehow.
-                None => panic!("Instruction ptr out of range ({})", instruction_ptr),
+                None => { error = Some(VmError::IpOutOfRange(instruction_ptr)); },
             }
             // Decrease the limti
             limit -= 1;
@@ -192,11 +674,17 @@ impl<'a> ProgramExecutor<'a> {
         // I don't forget to update the field on the struct so why not?!
         self.instruction_ptr = instruction_ptr;
 
+        // Something went wrong. We stay paused at the faulty instruction.
+        if let Some(e) = error {
+            self.state = ProgramState::Paused;
+            return Err(e);
+        }
+
         // Now let's look if captured any requests. We need to update
         // our inner state accordingly
         match request {
             // No request? Then pause!
-            None => { self.state = ProgramState::Paused; Request::Resume }
+            None => { self.state = ProgramState::Paused; Ok(Request::Resume) }
             // Otherwise...
             Some(x) => {
                 match x {
@@ -206,32 +694,98 @@ impl<'a> ProgramExecutor<'a> {
                     Request::Wait => { self.state = ProgramState::Waiting; },
                     Request::PerformChoice(_) => { self.state = ProgramState::WaitingForChoice; },
                 };
-                x
+                Ok(x)
+            },
+        }
+    }
+
+    fn wrong_state(&self, expected : ProgramState) -> VmError {
+        VmError::WrongState { expected, actual : self.state }
+    }
+
+    /// Capture the VM's state, so it can be resumed later
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            fingerprint : self.my_program.fingerprint,
+            instruction_ptr : self.instruction_ptr,
+            frame_stack : self.frame_stack.clone(),
+            value_stack : self.value_stack.clone(),
+            variables : self.variables.clone(),
+            visible_options : self.visible_options.clone(),
+            taken_options : self.taken_options.clone(),
+            state : self.state,
+        }
+    }
+
+    /// Limits how many calls deep the story may go. A call which would
+    /// go deeper fails with `VmError::StackOverflow`. The tail calls don't
+    /// count. `None` lets the stack grow as long as there's memory.
+    /// It's `DEFAULT_MAX_DEPTH` by default
+    pub fn set_max_depth(&mut self, max_depth : Option<usize>) {
+        self.max_depth = max_depth;
+    }
+
+    /// Where the VM is, as `dialogue+offset`. The dialogues are named
+    /// by the symbol table, see `Program::with_names`
+    pub fn location(&self) -> String {
+        self.my_program.dialogue_at(self.instruction_ptr)
+    }
+
+    /// The address of the instruction the VM executes next
+    pub fn instruction_ptr(&self) -> usize {
+        self.instruction_ptr
+    }
+
+    /// The story variables
+    pub fn variables(&self) -> &BTreeMap<String, Value> {
+        &self.variables
+    }
+
+    /// Write a story variable. That's how the host can tell
+    /// the story something.
+    pub fn set_variable(&mut self, name : &str, value : Value) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    /// The request the VM is currently waiting an answer for. That's
+    /// handy for a freshly resumed VM, because the client doesn't
+    /// remember what the VM was asking.
+    pub fn pending_request(&self) -> Request {
+        match self.state {
+            ProgramState::Paused => Request::Resume,
+            ProgramState::Waiting => Request::Wait,
+            ProgramState::Terminated => Request::Drop,
+            ProgramState::WaitingForChoice => {
+                match self.my_program.opcodes.get(self.instruction_ptr) {
+                    Some(Instruction::Branch(data)) => Request::PerformChoice(self.visible_options.iter().map(|x| data[*x].option_name.clone()).collect()),
+                    // See the specification of `ProgramState`
+                    _ => unreachable!("Detected a memory corruption"),
+                }
             },
         }
     }
 
     /// Send the "unpause" signal to the VM. This signal should be sent
     /// as an asnwer to the "Resume" request.
-    pub fn unpause(&mut self, limit : Option<usize>) -> Request<'a> {
+    pub fn unpause(&mut self, limit : Option<usize>) -> Result<Request, VmError> {
         match self.state {
             ProgramState::Paused => self.execute(limit),
-            _ => panic!("Can't unpause in current state"),
+            _ => Err(self.wrong_state(ProgramState::Paused)),
         }
     }
 
     /// Send the "accepted" signal to the VM. This signal should be sent
     /// as an answer to the "FlushAndWait" request.
-    pub fn done_printing(&mut self, limit : Option<usize>) -> Request<'a> {
+    pub fn done_printing(&mut self, limit : Option<usize>) -> Result<Request, VmError> {
         match self.state {
             ProgramState::Waiting => self.execute(limit),
-            _ => panic!("I wasn't waiting for you to print"),
+            _ => Err(self.wrong_state(ProgramState::Waiting)),
         }
     }
 
     /// Send the "choice(id)" signal to the VM. This signal should be sent
     /// as an answer to the "PerformChoice(x)" request.
-    pub fn choose(&mut self, option_id : usize, limit : Option<usize>) -> Request<'a> {
+    pub fn choose(&mut self, option_id : usize, limit : Option<usize>) -> Result<Request, VmError> {
         match self.state {
             ProgramState::WaitingForChoice => {
                 // Right now the pointer is pointing at the choice instruction
@@ -241,11 +795,15 @@ impl<'a> ProgramExecutor<'a> {
                 let new_ptr = {
                     match self.my_program.opcodes.get(choice_opcode_ptr) {
                         Some(Instruction::Branch(data)) => {
-                            match data.get(option_id) {
-                                Some(x) => x.jmp_address,
-                                // Now nobody said that the instructions will
-                                // be correct :/
-                                None => panic!("Choice out of range. The opcodes must be corrupted."),
+                            // The client only sees the visible options
+                            match self.visible_options.get(option_id) {
+                                Some(x) => {
+                                    if data[*x].once { self.taken_options.insert((choice_opcode_ptr, *x)); }
+                                    data[*x].jmp_address
+                                },
+                                // The client picked something weird. We keep
+                                // waiting for a proper choice
+                                None => return Err(VmError::InvalidChoice(option_id, self.visible_options.len())),
                             }
                         },
                         // Now, tbh. I don't know how to complain if something
@@ -261,7 +819,7 @@ impl<'a> ProgramExecutor<'a> {
                 self.instruction_ptr = new_ptr;
                 self.execute(limit)
             },
-            _ => panic!("I wasn't waiting for you to pick an option"),
+            _ => Err(self.wrong_state(ProgramState::WaitingForChoice)),
         }
     }
 }
@@ -0,0 +1,108 @@
+use texted_adventure::{ AsmError, Executable, assemble, compile_source, disassemble, load_assembly, save_assembly };
+
+// The dialogues cover every instruction the translator emits
+const SOURCES : &[&str] = &[
+    r#"
+main:
+  - print: "Hello"
+  - wait
+  - call: other
+other:
+  - print: "Bye"
+"#,
+    r#"
+main:
+  - set: { gold: 3, name: '"Bob \"the\" builder"' }
+  - print: 'Hi {name}! You have {gold * 2 - 1} coins'
+  - if: gold >= 5 && !(name == "Al") || -gold < 0
+    then:
+      - print: "Rich"
+    else:
+      - print: "Poor"
+  - set: { gold: gold % 2 }
+"#,
+    r#"
+main:
+  - set: { met: false }
+  - call: hub
+hub:
+  - choose:
+    - Ask about the mayor:
+      - set: { met: true }
+      - call: hub
+      once: true
+    - Leave:
+      - print: "Bye"
+      when: met
+    - Nothing to ask: []
+      fallback: true
+"#,
+    r#"
+"Talk to Bob":
+  - print: "Tabs\tand\nnew lines"
+L1:
+  - call: "Talk to Bob"
+"#,
+];
+
+fn round_trip(exe : &Executable) {
+    let text = disassemble(exe);
+    let back = assemble(&text).unwrap_or_else(|e| panic!("{}\n{}", e, text));
+    assert_eq!(&back, exe, "{}", text);
+    assert_eq!(disassemble(&back), text);
+}
+
+#[test]
+fn linked_dialogues_survive_the_round_trip() {
+    for src in SOURCES.iter() {
+        round_trip(&compile_source(src).unwrap());
+    }
+}
+
+#[test]
+fn hand_written_assembly() {
+    let exe = assemble(
+        r#"
+; the entry table
+.entry main
+.entry "second one" two
+
+main:
+    push_ptr back   ; come back here
+    jmp two
+back:
+    ret
+two:
+    msg "hi"
+    jmp 2           ; plain addresses work too
+"#
+    ).unwrap();
+    assert_eq!(exe.entry_points["main"], 0);
+    assert_eq!(exe.entry_points["second one"], 3);
+    round_trip(&exe);
+}
+
+#[test]
+fn errors_point_at_the_line() {
+    let err = |src : &str| assemble(src).unwrap_err();
+    assert_eq!(err(".entry main\nmain:\n    jmp nowhere\n").line, 3);
+    assert_eq!(err("main:\nmain:\n").line, 2);
+    assert_eq!(err("    choose\n        option \"a\" 0\n").line, 2);
+    assert_eq!(err("    push\n"), AsmError { line : 1, message : "expected a value".to_string() });
+    assert_eq!(err("    frobnicate\n").line, 1);
+}
+
+#[test]
+fn private_dialogues_keep_their_names() {
+    let exe = compile_source(SOURCES[2]).unwrap();
+    assert_eq!(exe.symbols.keys().collect::<Vec<_>>(), vec!["main", "hub"]);
+    assert!(disassemble(&exe).contains(".symbol hub\n"));
+    assert_eq!(load_assembly(&save_assembly(&exe)).unwrap(), exe);
+}
+
+#[test]
+fn old_yaml_assembly_has_no_symbols() {
+    let exe = load_assembly("---\nmain: 0\n---\n- ret\n").unwrap();
+    assert_eq!(exe.entry_points["main"], 0);
+    assert!(exe.symbols.is_empty());
+}
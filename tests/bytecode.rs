@@ -0,0 +1,102 @@
+use texted_adventure::{ Executable, LoadError, compile_source, load_bytecode, load_executable, save_bytecode };
+
+// Every opcode tag, every value tag and every branch flag
+const SOURCE : &str = r#"
+main:
+  - set: { gold: 3, name: "'Bob'", rich: false }
+  - print: 'Hi {name}! You have {gold * 2 - 1 + 0} coins'
+  - wait
+  - if: gold >= 5 && !(name == "Al") || -gold < 0 && gold / 2 != gold % 2 || gold <= 1 || gold > 9
+    then:
+      - print: "Repeated text"
+    else:
+      - print: "Repeated text"
+  - call: hub
+  - print: "Bye"
+hub:
+  - choose:
+    - Ask:
+      - set: { rich: true }
+      once: true
+    - Leave:
+      - print: "Repeated text"
+      when: rich
+    - Nothing: []
+      fallback: true
+"#;
+
+fn story() -> Executable {
+    compile_source(SOURCE).unwrap()
+}
+
+#[test]
+fn executables_survive_the_round_trip() {
+    let exe = story();
+    let bytes = save_bytecode(&exe);
+    assert_eq!(load_bytecode(&bytes).unwrap(), exe);
+    assert_eq!(load_executable(&bytes).unwrap(), exe);
+}
+
+#[test]
+fn private_dialogues_are_in_the_symbol_table() {
+    let exe = load_bytecode(&save_bytecode(&story())).unwrap();
+    assert_eq!(exe.entry_points.keys().collect::<Vec<_>>(), vec!["main"]);
+    assert_eq!(exe.symbols.keys().collect::<Vec<_>>(), vec!["main", "hub"]);
+}
+
+#[test]
+fn version_1_has_no_symbol_table() {
+    let exe = Executable { symbols : Default::default(), ..story() };
+    let mut bytes = save_bytecode(&exe);
+    // the empty symbol table goes
+    bytes.truncate(bytes.len() - 4);
+    bytes[4..6].copy_from_slice(&1u16.to_le_bytes());
+    assert_eq!(load_bytecode(&bytes).unwrap(), exe);
+}
+
+#[test]
+fn strings_are_stored_once() {
+    let bytes = save_bytecode(&story());
+    let needle = b"Repeated text";
+    let count = bytes.windows(needle.len()).filter(|x| x == needle).count();
+    assert_eq!(count, 1);
+}
+
+#[test]
+fn truncated_bytecode_is_rejected() {
+    let bytes = save_bytecode(&story());
+    for len in 4..bytes.len() {
+        assert!(matches!(load_bytecode(&bytes[..len]), Err(LoadError::Truncated)), "{} bytes", len);
+    }
+    assert!(matches!(load_bytecode(&bytes[..3]), Err(LoadError::NotBytecode)));
+}
+
+#[test]
+fn trailing_bytes_are_rejected() {
+    let mut bytes = save_bytecode(&story());
+    let len = bytes.len();
+    bytes.push(0);
+    assert!(matches!(load_bytecode(&bytes), Err(LoadError::TrailingBytes(x)) if x == len));
+}
+
+#[test]
+fn unknown_versions_are_rejected() {
+    let mut bytes = save_bytecode(&story());
+    bytes[4..6].copy_from_slice(&999u16.to_le_bytes());
+    assert!(matches!(load_bytecode(&bytes), Err(LoadError::UnsupportedVersion(999))));
+}
+
+#[test]
+fn missing_strings_are_rejected() {
+    // No strings, but the only entry is named with string 5
+    let mut bytes = save_bytecode(&Executable { entry_points : Default::default(), symbols : Default::default(), opcodes : Vec::new() });
+    // the magic and the version stay
+    bytes.truncate(6);
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend(1u32.to_le_bytes());
+    bytes.extend(5u32.to_le_bytes());
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend(0u32.to_le_bytes());
+    assert!(matches!(load_bytecode(&bytes), Err(LoadError::BadString(5))));
+}
@@ -0,0 +1,51 @@
+use texted_adventure::{ CompileErrorKind, check_files };
+
+mod common;
+
+use common::write_story;
+
+#[test]
+fn second_definition_keeps_the_calls_of_the_first() {
+    let paths = write_story(
+        "checker_duplicate",
+        &[
+            ("a.diag", "main:\n  - call: helper\nhelper:\n  - print: \"hi\"\n"),
+            ("b.diag", "main:\n  - print: \"b\"\n"),
+        ]
+    );
+    let problems = check_files(&paths);
+    assert_eq!(problems.len(), 1, "{:?}", problems.iter().map(|x| x.to_string()).collect::<Vec<_>>());
+    assert!(matches!(&problems[0].error.kind, CompileErrorKind::DuplicateProc(x) if x == "main"));
+    assert_eq!(problems[0].path, paths[1]);
+}
+
+#[test]
+fn second_definition_adds_no_recursion() {
+    let paths = write_story(
+        "checker_recursion",
+        &[
+            ("a.diag", "main:\n  - print: \"a\"\n"),
+            ("b.diag", "main:\n  - call: main\n"),
+        ]
+    );
+    let problems = check_files(&paths);
+    assert_eq!(problems.len(), 1, "{:?}", problems.iter().map(|x| x.to_string()).collect::<Vec<_>>());
+    assert!(matches!(&problems[0].error.kind, CompileErrorKind::DuplicateProc(x) if x == "main"));
+}
+
+#[test]
+fn broken_files_add_no_follow_on_problems() {
+    let paths = write_story(
+        "checker_broken",
+        &[
+            ("main.diag", "import: [forest.diag, nowhere.diag]\nmain:\n  - call: forest::intro\n  - call: nowhere::start\n  - call: town\ntown:\n  - print: \"Town\"\n"),
+            ("forest.diag", "pub: intro\nintro: [\n"),
+        ]
+    );
+    let problems = check_files(&paths[..1]);
+    assert_eq!(problems.len(), 2, "{:?}", problems.iter().map(|x| x.to_string()).collect::<Vec<_>>());
+    assert_eq!(problems[0].path, paths[1]);
+    assert!(matches!(&problems[0].error.kind, CompileErrorKind::Yaml(_)));
+    assert_eq!(problems[1].path, paths[0]);
+    assert!(matches!(&problems[1].error.kind, CompileErrorKind::CantRead(x, _) if x.ends_with("nowhere.diag")));
+}
@@ -0,0 +1,31 @@
+// Helpers shared by the tests. Not every test uses all of them
+#![allow(dead_code)]
+
+use texted_adventure::{ Program, ProgramExecutor, compile_source };
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Writes the files into a directory of their own and returns their paths
+pub fn write_story(dir : &str, files : &[(&str, &str)]) -> Vec<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("texted_adventure_{}_{}", dir, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    files.iter()
+    .map(
+        |(name, src)| {
+            let path = dir.join(name);
+            fs::write(&path, src).unwrap();
+            path
+        }
+    )
+    .collect()
+}
+
+pub fn program(src : &str) -> Arc<Program> {
+    Arc::new(compile_source(src).unwrap().into_program("main").unwrap())
+}
+
+pub fn start(src : &str) -> ProgramExecutor {
+    program(src).run()
+}
@@ -0,0 +1,59 @@
+use texted_adventure::{ Failure, Instruction, Limits, VmError, explore };
+
+mod common;
+
+use common::program;
+
+#[test]
+fn every_path_is_played() {
+    let program = program(
+        r#"
+main:
+  - set: { gold: 0 }
+  - call: hub
+hub:
+  - choose:
+    - Wait:
+      - call: hub
+    - Divide:
+      - set: { gold: 1 / gold }
+    - Leave:
+      - print: "Bye"
+    - Stay:
+      - print: "Stayed"
+"#
+    );
+    let report = explore(&program, &[], Limits::default());
+    assert_eq!(report.paths, 3);
+    assert_eq!(report.endings.len(), 2);
+    assert_eq!(report.endings["Bye"], 1);
+    assert_eq!(report.endings["Stayed"], 1);
+    assert_eq!(report.loops, 1);
+    assert_eq!(report.max_depth, 1);
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(report.failures[0].0, vec!["Divide".to_string()]);
+    assert!(matches!(report.failures[0].1, Failure::Vm(VmError::DivisionByZero(_))));
+    // Only the rest of the failed option never runs. The `ret`s and
+    // the jumps left after the tail calls can't run, so they aren't reported
+    let division = program.opcodes().iter().position(|x| matches!(x, Instruction::BinOp(_))).unwrap();
+    assert_eq!(report.unreachable, vec![division + 1, division + 2]);
+}
+
+#[test]
+fn code_which_never_runs_is_reported() {
+    let program = program(
+        r#"
+main:
+  - set: { rich: false }
+  - if: rich
+    then:
+      - print: "Never"
+  - print: "Always"
+"#
+    );
+    let report = explore(&program, &[], Limits::default());
+    assert_eq!(report.paths, 1);
+    assert_eq!(report.endings["Always"], 1);
+    assert!(!report.unreachable.is_empty());
+    assert!(report.unreachable.iter().any(|x| program.opcodes()[*x] == Instruction::Msg("Never".to_string())));
+}
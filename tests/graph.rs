@@ -0,0 +1,59 @@
+use texted_adventure::{ Graph, story_graph };
+
+mod common;
+
+// An option which calls nothing goes on after the choice, where `y` is called
+const STORY : &str = r#"
+main:
+  - choose:
+    - Say "hi": []
+    - Leave:
+      - call: x
+      when: true
+  - call: y
+x:
+  - print: "x"
+y:
+  - print: "y"
+"#;
+
+fn graph(name : &str) -> Graph {
+    story_graph(&common::write_story(&format!("graph_{}", name), &[("g.diag", STORY)])).unwrap()
+}
+
+#[test]
+fn dot_output() {
+    assert_eq!(
+        graph("dot").to_dot(),
+        r#"digraph story {
+    n0 [label="main", shape=box];
+    n1 [label="g::x", shape=box];
+    n2 [label="g::y", shape=box];
+    n3 [label="choose", shape=diamond];
+    n4 [label="continue", shape=circle];
+    n0 -> n3;
+    n3 -> n4 [label="Say \"hi\""];
+    n3 -> n1 [label="Leave", style=dashed];
+    n4 -> n2;
+}
+"#
+    );
+}
+
+#[test]
+fn mermaid_output() {
+    assert_eq!(
+        graph("mermaid").to_mermaid(),
+        r#"flowchart TD
+    n0["main"]
+    n1["g::x"]
+    n2["g::y"]
+    n3{"choose"}
+    n4(("continue"))
+    n0 --> n3
+    n3 -->|"Say #quot;hi#quot;"| n4
+    n3 -.->|"Leave"| n1
+    n4 --> n2
+"#
+    );
+}
@@ -0,0 +1,66 @@
+use texted_adventure::{ CompileErrorKind, Executable, Request, compile_files };
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+fn fixture(path : &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(path)
+}
+
+// The messages printed from `main` to the end
+fn play(exe : Executable) -> Vec<String> {
+    let mut exec = Arc::new(exe.into_program("main").unwrap()).run();
+    let mut messages = Vec::new();
+    loop {
+        match exec.unpause(None).unwrap() {
+            Request::PrintMessage(x) => messages.push(x),
+            Request::Drop => return messages,
+            _ => panic!("the story doesn't ask anything"),
+        }
+    }
+}
+
+#[test]
+fn imported_files_are_linked_in() {
+    let exe = compile_files(&[fixture("story/main.diag")]).unwrap();
+    assert_eq!(play(exe), vec!["Start", "The forest path", "The town square", "The forest path"]);
+}
+
+#[test]
+fn files_are_compiled_once() {
+    // forest.diag is imported twice and listed on top of that
+    let paths = [fixture("story/main.diag"), fixture("story/chapters/forest.diag")];
+    let exe = compile_files(&paths).unwrap();
+    assert_eq!(exe.symbols.keys().collect::<Vec<_>>(), vec!["main", "forest::intro", "forest::path", "town::square"]);
+}
+
+#[test]
+fn missing_imports_are_reported() {
+    let err = compile_files(&[fixture("missing/main.diag")]).err().unwrap();
+    assert!(matches!(&err.error.kind, CompileErrorKind::CantRead(x, _) if x.ends_with("nowhere.diag")));
+    assert_eq!(err.path, fixture("missing/main.diag"));
+    assert_eq!(err.error.pos.line, 1);
+}
+
+#[test]
+fn only_public_dialogues_are_entry_points() {
+    let exe = compile_files(&[fixture("story/main.diag")]).unwrap();
+    assert_eq!(exe.entry_points.keys().collect::<Vec<_>>(), vec!["main", "forest::intro", "town::square"]);
+    // nor can the private ones be started
+    assert!(exe.into_program("forest::path").is_none());
+}
+
+#[test]
+fn private_dialogues_of_other_modules_are_rejected() {
+    let err = compile_files(&[fixture("private/main.diag")]).err().unwrap();
+    assert!(matches!(&err.error.kind, CompileErrorKind::PrivateDialogue(x) if x == "forest::path"));
+    assert_eq!(err.path, fixture("private/main.diag"));
+    assert_eq!((err.error.pos.line, err.error.pos.col), (3, 5));
+}
+
+#[test]
+fn modules_with_the_same_name_are_rejected() {
+    let err = compile_files(&[fixture("duplicate/main.diag")]).err().unwrap();
+    assert!(matches!(&err.error.kind, CompileErrorKind::DuplicateModule(x) if x == "forest"));
+    assert_eq!(err.path, fixture("duplicate/main.diag").parent().unwrap().join("b/forest.diag"));
+}
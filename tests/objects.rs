@@ -0,0 +1,35 @@
+use texted_adventure::{ LoadError, compile_files, compile_object, link_objects, load_object, save_object };
+
+use std::path::PathBuf;
+
+fn fixture(path : &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(path)
+}
+
+const FILES : &[&str] = &["story/main.diag", "story/chapters/forest.diag", "story/chapters/town.diag"];
+
+#[test]
+fn objects_survive_the_round_trip() {
+    for path in FILES.iter() {
+        let text = save_object(&compile_object(fixture(path)).unwrap());
+        let back = load_object(&text).unwrap();
+        assert_eq!(save_object(&back), text);
+    }
+}
+
+#[test]
+fn linked_objects_make_the_same_executable() {
+    // the objects go through the files, the way `compile -c` and `link` do it
+    let objects =
+        FILES.iter()
+        .map(|x| load_object(&save_object(&compile_object(fixture(x)).unwrap())).unwrap())
+        .collect()
+    ;
+    assert_eq!(link_objects(objects).unwrap(), compile_files(&[fixture("story/main.diag")]).unwrap());
+}
+
+#[test]
+fn broken_objects_are_rejected() {
+    let text = save_object(&compile_object(fixture("story/main.diag")).unwrap());
+    assert!(matches!(load_object(&text.replace("module:", "modul:")), Err(LoadError::BadObject)));
+}
@@ -0,0 +1,171 @@
+use texted_adventure::{ Executable, Instruction, Request, Value, compile_source, load_assembly, optimize, save_assembly };
+
+use std::sync::Arc;
+
+// Plays the story picking the given options in order. Returns the
+// printed messages. The story has to end once the options run out
+fn play(exe : Executable, variables : &[(&str, Value)], choices : &[usize]) -> Vec<String> {
+    let mut exec = Arc::new(exe.into_program("main").unwrap()).run();
+    variables.iter().for_each(|(name, value)| exec.set_variable(name, value.clone()));
+    let mut choices = choices.iter();
+    let mut messages = Vec::new();
+    let mut request = Request::Resume;
+    loop {
+        request = {
+            match request {
+                Request::PrintMessage(x) => { messages.push(x); exec.unpause(None).unwrap() },
+                Request::Resume => exec.unpause(None).unwrap(),
+                Request::Wait => exec.done_printing(None).unwrap(),
+                Request::PerformChoice(_) => exec.choose(*choices.next().expect("ran out of choices"), None).unwrap(),
+                Request::Drop => break,
+            }
+        };
+    }
+    assert!(choices.next().is_none(), "the story ended too early");
+    messages
+}
+
+// The optimized story prints the same and ends the same way
+fn assert_same(src : &str, variables : &[(&str, Value)], choices : &[usize]) {
+    let plain = play(compile_source(src).unwrap(), variables, choices);
+    let optimized = play(optimize(compile_source(src).unwrap()), variables, choices);
+    assert_eq!(optimized, plain);
+}
+
+// No jump is left which only goes to the next instruction
+fn assert_no_jumps_to_next(exe : &Executable) {
+    for (i, opcode) in exe.opcodes.iter().enumerate() {
+        assert_ne!(*opcode, Instruction::Jmp(i + 1), "at {}", i);
+    }
+}
+
+const BRANCHES : &str = r#"
+extern: [gold, poor]
+main:
+  - if: gold > 5 && !poor || gold == 0
+    then:
+      - print: "Rich or broke"
+      - if: gold == 0
+        then:
+          - print: "Broke"
+        else:
+          - print: "Rich"
+    else:
+      - print: "Somewhere between"
+  - if: poor || gold < 3
+    then:
+      - print: "Poor"
+  - wait
+  - print: "Done"
+"#;
+
+const CHOICES : &str = r#"
+main:
+  - set: { asked: false }
+  - call: hub
+hub:
+  - choose:
+    - Ask:
+      - set: { asked: true }
+      - choose:
+        - Again:
+          - call: hub
+        - Stop:
+          - print: "Stopped"
+      once: true
+    - Leave:
+      - print: "Bye"
+      when: asked
+    - Nothing left:
+      - print: "Nothing"
+      fallback: true
+"#;
+
+#[test]
+fn branches_do_the_same() {
+    for gold in [0, 2, 4, 9] {
+        for poor in [false, true] {
+            let variables = [("gold", Value::Int(gold)), ("poor", Value::Bool(poor))];
+            assert_same(BRANCHES, &variables, &[]);
+        }
+    }
+    assert_no_jumps_to_next(&optimize(compile_source(BRANCHES).unwrap()));
+}
+
+#[test]
+fn choices_do_the_same() {
+    // Ask is gone after it's taken once. Then Leave shows up, and Nothing
+    // left is only taken once nothing else is there
+    assert_same(CHOICES, &[], &[0, 1]);
+    assert_same(CHOICES, &[], &[0, 0, 0]);
+    let exe = optimize(compile_source(CHOICES).unwrap());
+    assert_no_jumps_to_next(&exe);
+    assert!(exe.opcodes.len() < compile_source(CHOICES).unwrap().opcodes.len());
+}
+
+#[test]
+fn fallback_does_the_same() {
+    let src = r#"
+main:
+  - call: hub
+  - print: "Back"
+hub:
+  - choose:
+    - Once:
+      - print: "First"
+      - call: hub
+      once: true
+    - Fallback:
+      - print: "Last"
+      fallback: true
+"#;
+    assert_same(src, &[], &[0]);
+    assert_eq!(play(optimize(compile_source(src).unwrap()), &[], &[0]), vec!["First", "Last", "Back"]);
+}
+
+#[test]
+fn tail_calls_lose_their_ret() {
+    let src = r#"
+main:
+  - print: "a"
+  - call: other
+other:
+  - print: "b"
+"#;
+    let plain = compile_source(src).unwrap();
+    assert_eq!(plain.opcodes[1..3], [Instruction::Jmp(3), Instruction::Ret]);
+
+    // The `ret` after the tail call never runs, and then the call
+    // is a jump to the next instruction
+    let exe = optimize(compile_source(src).unwrap());
+    assert_eq!(exe.opcodes, vec![Instruction::Msg("a".to_string()), Instruction::Msg("b".to_string()), Instruction::Ret]);
+    assert_eq!(exe.symbols["main"], 0);
+    assert_eq!(exe.symbols["other"], 1);
+    assert_same(src, &[], &[]);
+}
+
+#[test]
+fn emptied_dialogues_leave_the_symbol_table() {
+    // `relay` only passes the call on, so the calls go straight past it
+    let src = r#"
+main:
+  - call: relay
+  - print: "Back"
+relay:
+  - call: other
+other:
+  - print: "Other"
+"#;
+    let exe = optimize(compile_source(src).unwrap());
+    assert!(!exe.symbols.contains_key("relay"));
+    assert!(exe.symbols.contains_key("other"));
+    assert_same(src, &[], &[]);
+}
+
+#[test]
+fn optimized_options_survive_the_yaml_round_trip() {
+    // the option's call is threaded straight to the start of `main`
+    let exe = optimize(compile_source("main: [{choose: [{again: [{call: main}]}]}]").unwrap());
+    assert!(matches!(&exe.opcodes[0], Instruction::Branch(x) if x[0].jmp_address == 0));
+    assert_eq!(load_assembly(&save_assembly(&exe)).unwrap(), exe);
+}
@@ -0,0 +1,108 @@
+use texted_adventure::{ ProgramExecutor, Request, Value, VmError, load_snapshot, save_snapshot };
+
+mod common;
+
+fn start(src : &str, variables : &[(&str, Value)]) -> ProgramExecutor {
+    let mut exec = common::start(src);
+    variables.iter().for_each(|(name, value)| exec.set_variable(name, value.clone()));
+    exec
+}
+
+fn options(request : Result<Request, VmError>) -> Vec<String> {
+    match request {
+        Ok(Request::PerformChoice(x)) => x,
+        _ => panic!("expected a choice"),
+    }
+}
+
+fn message(request : Result<Request, VmError>) -> String {
+    match request {
+        Ok(Request::PrintMessage(x)) => x,
+        _ => panic!("expected a message"),
+    }
+}
+
+const DOOR : &str = r#"
+extern: [has_key, strong]
+main:
+  - choose:
+    - Open the door:
+      - print: "Opened"
+      when: has_key
+    - Break the door:
+      - print: "Broken"
+      when: strong && !has_key
+    - Knock:
+      - print: "Knocked"
+    - Leave:
+      - print: "Left"
+"#;
+
+#[test]
+fn hidden_options_are_left_out() {
+    let mut exec = start(DOOR, &[("has_key", Value::Bool(false)), ("strong", Value::Bool(false))]);
+    assert_eq!(options(exec.unpause(None)), vec!["Knock", "Leave"]);
+    let mut exec = start(DOOR, &[("has_key", Value::Bool(true)), ("strong", Value::Bool(true))]);
+    assert_eq!(options(exec.unpause(None)), vec!["Open the door", "Knock", "Leave"]);
+    let mut exec = start(DOOR, &[("has_key", Value::Bool(false)), ("strong", Value::Bool(true))]);
+    assert_eq!(options(exec.unpause(None)), vec!["Break the door", "Knock", "Leave"]);
+}
+
+#[test]
+fn picks_are_counted_among_the_visible_options() {
+    let mut exec = start(DOOR, &[("has_key", Value::Bool(false)), ("strong", Value::Bool(true))]);
+    exec.unpause(None).unwrap();
+    assert_eq!(message(exec.choose(0, None)), "Broken");
+
+    let mut exec = start(DOOR, &[("has_key", Value::Bool(false)), ("strong", Value::Bool(false))]);
+    exec.unpause(None).unwrap();
+    assert_eq!(exec.choose(2, None).err().unwrap(), VmError::InvalidChoice(2, 2));
+    // the choice is still waiting for a proper pick
+    assert_eq!(message(exec.choose(1, None)), "Left");
+}
+
+const HUB : &str = r#"
+main:
+  - call: hub
+hub:
+  - choose:
+    - Ask about the mayor:
+      - call: hub
+      once: true
+    - Ask about the weather:
+      - call: hub
+      once: true
+    - Nothing left to ask:
+      - print: "Bye"
+      fallback: true
+"#;
+
+#[test]
+fn used_up_options_stay_used_up_after_loading() {
+    let mut exec = start(HUB, &[]);
+    assert_eq!(options(exec.unpause(None)), vec!["Ask about the mayor", "Ask about the weather"]);
+    assert_eq!(options(exec.choose(0, None)), vec!["Ask about the weather"]);
+
+    let snapshot = load_snapshot(&save_snapshot(&exec.snapshot())).unwrap();
+    let program = common::program(HUB);
+    let mut exec = program.resume(snapshot).unwrap();
+    assert!(matches!(exec.pending_request(), Request::PerformChoice(x) if x == vec!["Ask about the weather"]));
+    // the mayor doesn't come back once the weather is used up too
+    assert_eq!(message(exec.choose(0, None)), "Bye");
+}
+
+#[test]
+fn fallback_is_taken_once_everything_is_used_up() {
+    let mut exec = start(HUB, &[]);
+    // the fallback isn't offered while there's something else
+    assert_eq!(options(exec.unpause(None)).len(), 2);
+    assert_eq!(options(exec.choose(1, None)), vec!["Ask about the mayor"]);
+    assert_eq!(message(exec.choose(0, None)), "Bye");
+    assert!(matches!(exec.unpause(None), Ok(Request::Drop)));
+}
+
+#[test]
+fn choice_without_options_fails_without_a_fallback() {
+    let mut exec = start("extern: open\nmain:\n  - choose:\n    - Go:\n      - print: \"Gone\"\n      when: open\n", &[("open", Value::Bool(false))]);
+    assert!(matches!(exec.unpause(None), Err(VmError::NoVisibleOptions(_))));
+}
@@ -0,0 +1,58 @@
+use texted_adventure::{ CompileErrorKind, compile_files_reachable };
+
+mod common;
+
+use std::path::PathBuf;
+
+const STORY : &str = r#"
+pub: [epilogue]
+main:
+  - call: intro
+  - print: "The end"
+intro:
+  - call: road
+road:
+  - print: "A road"
+unused:
+  - call: road
+epilogue:
+  - call: credits
+credits:
+  - print: "Credits"
+"#;
+
+fn write_story(dir : &str) -> PathBuf {
+    common::write_story(&format!("reachable_{}", dir), &[("story.diag", STORY)]).remove(0)
+}
+
+fn roots(names : &[&str]) -> Vec<String> {
+    names.iter().map(|x| x.to_string()).collect()
+}
+
+#[test]
+fn only_the_reachable_dialogues_are_kept() {
+    let (exe, left_out) = compile_files_reachable(&[write_story("main")], &roots(&["main"])).unwrap();
+    assert_eq!(exe.symbols.keys().collect::<Vec<_>>(), vec!["main", "story::intro", "story::road"]);
+    assert_eq!(exe.entry_points.keys().collect::<Vec<_>>(), vec!["main"]);
+    assert_eq!(left_out, vec!["story::unused", "story::epilogue", "story::credits"]);
+}
+
+#[test]
+fn every_root_is_kept() {
+    let (exe, left_out) = compile_files_reachable(&[write_story("roots")], &roots(&["main", "story::epilogue"])).unwrap();
+    assert_eq!(exe.entry_points.keys().collect::<Vec<_>>(), vec!["main", "story::epilogue"]);
+    assert_eq!(left_out, vec!["story::unused"]);
+}
+
+#[test]
+fn unknown_roots_are_rejected() {
+    let err = compile_files_reachable(&[write_story("unknown")], &roots(&["mian"])).err().unwrap();
+    assert!(matches!(&err.error.kind, CompileErrorKind::UnknownDialogue(x) if x == "mian"));
+}
+
+#[test]
+fn private_roots_are_rejected() {
+    let err = compile_files_reachable(&[write_story("private")], &roots(&["story::road"])).err().unwrap();
+    assert!(matches!(&err.error.kind, CompileErrorKind::PrivateDialogue(x) if x == "story::road"));
+    assert_eq!(err.error.pos.line, 8);
+}
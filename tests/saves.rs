@@ -0,0 +1,57 @@
+use texted_adventure::{ Instruction, Program, Request, VmError, load_snapshot, save_snapshot };
+
+mod common;
+
+use common::program;
+use std::sync::Arc;
+
+const STORY : &str = r#"
+main:
+  - set: { gold: 1 }
+  - call: hub
+hub:
+  - choose:
+    - Work:
+      - set: { gold: gold + 1 }
+      - call: hub
+      once: true
+    - Leave:
+      - print: "Leaving with {gold} coins"
+"#;
+
+#[test]
+fn saved_game_goes_on_where_it_stopped() {
+    let program = program(STORY);
+    let mut exec = program.run();
+    assert!(matches!(exec.unpause(None), Ok(Request::PerformChoice(_))));
+    assert!(matches!(exec.choose(0, None), Ok(Request::PerformChoice(x)) if x == vec!["Leave".to_string()]));
+
+    let save = save_snapshot(&exec.snapshot());
+    let snapshot = load_snapshot(&save).unwrap();
+    assert_eq!(snapshot, exec.snapshot());
+
+    // The game is loaded into another session of the same story
+    let mut exec = program.resume(snapshot).unwrap();
+    assert!(matches!(exec.pending_request(), Request::PerformChoice(x) if x == vec!["Leave".to_string()]));
+    assert!(matches!(exec.choose(0, None), Ok(Request::PrintMessage(x)) if x == "Leaving with 2 coins"));
+}
+
+#[test]
+fn saves_of_other_stories_are_rejected() {
+    let mut exec = program(STORY).run();
+    exec.unpause(None).unwrap();
+    let snapshot = load_snapshot(&save_snapshot(&exec.snapshot())).unwrap();
+
+    let other = program(&STORY.replace("coins", "gold pieces"));
+    assert!(matches!(other.resume(snapshot.clone()), Err(VmError::FingerprintMismatch { .. })));
+    // the same story compiled again takes the save
+    assert!(program(STORY).resume(snapshot).is_ok());
+}
+
+#[test]
+fn fingerprint_is_stable() {
+    // FNV-1a of the opcodes written as bytecode. It must never change,
+    // or the saves made by the older builds stop loading
+    let program = Arc::new(Program::new(vec![Instruction::Msg("hi".to_string()), Instruction::Ret], 0));
+    assert_eq!(program.run().snapshot().fingerprint, 0x09a2ba4c29d3d548);
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use std::path::{ Path, PathBuf };
+use std::process::{ Command, Stdio };
+
+// `spin` calls itself as the last thing it does, so it's a plain
+// jump loop. The player is never asked anything
+const SPIN : &str = r#"
+main:
+  - print: "start"
+  - call: spin
+spin:
+  - set: { x: 1 }
+  - call: spin
+"#;
+
+fn write_story(name : &str) -> PathBuf {
+    common::write_story(&format!("step_limit_{}", name), &[("story.diag", SPIN)]).remove(0)
+}
+
+// Runs the engine and returns the error it stopped with
+fn stopped_with(args : &[&str], path : &Path) -> String {
+    let output =
+        Command::new(env!("CARGO_BIN_EXE_texted_adventure"))
+        .args(args)
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+    ;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stderr.lines().find(|x| x.starts_with("error: ")).unwrap_or_else(|| panic!("no error in {}", stderr)).to_string()
+}
+
+#[test]
+fn jump_loop_is_stopped_in_its_dialogue() {
+    let error = stopped_with(&["crun", "--step-limit", "1000"], &write_story("crun"));
+    assert!(error.contains("ran 1000 instructions"), "{}", error);
+    assert!(error.contains("(story::spin+"), "{}", error);
+}
+
+#[test]
+fn optimized_jump_loop_is_stopped_in_its_dialogue() {
+    let path = write_story("optimized");
+    let output = Command::new(env!("CARGO_BIN_EXE_texted_adventure")).args(["compile", "-O", "-b"]).arg(&path).output().unwrap();
+    assert!(output.status.success());
+    let error = stopped_with(&["run", "--step-limit", "1000"], &path.with_extension("bin"));
+    assert!(error.contains("(story::spin"), "{}", error);
+}
@@ -0,0 +1,76 @@
+use texted_adventure::{ Instruction, ProgramExecutor, Request, compile_source };
+
+mod common;
+
+use common::start;
+use std::sync::Arc;
+
+// Runs until the VM asks for something besides printing. Returns
+// the printed messages and the request
+fn run_until_input(exec : &mut ProgramExecutor, mut request : Request) -> (Vec<String>, Request) {
+    let mut messages = Vec::new();
+    loop {
+        request = {
+            match request {
+                Request::PrintMessage(x) => { messages.push(x); exec.unpause(None).unwrap() },
+                Request::Resume => exec.unpause(None).unwrap(),
+                x => return (messages, x),
+            }
+        };
+    }
+}
+
+#[test]
+fn looping_hub_keeps_the_stack_small() {
+    let mut exec = start(
+        r#"
+main:
+  - set: { visits: 0 }
+  - call: hub
+hub:
+  - set: { visits: visits + 1 }
+  - choose:
+    - Again:
+      - call: hub
+    - Maybe:
+      - if: visits > 0
+        then:
+          - call: hub
+    - Leave:
+      - print: "Bye"
+"#
+    );
+    let (_, mut request) = run_until_input(&mut exec, Request::Resume);
+    let depth = exec.snapshot().frame_stack.len();
+    for i in 0..10_000 {
+        assert!(matches!(request, Request::PerformChoice(_)));
+        let next = exec.choose(i % 2, None).unwrap();
+        request = run_until_input(&mut exec, next).1;
+        assert_eq!(exec.snapshot().frame_stack.len(), depth);
+    }
+    let next = exec.choose(2, None).unwrap();
+    let (messages, request) = run_until_input(&mut exec, next);
+    assert_eq!(messages, vec!["Bye".to_string()]);
+    assert!(matches!(request, Request::Drop));
+}
+
+#[test]
+fn only_tail_calls_become_jumps() {
+    let exe = compile_source(
+        r#"
+main:
+  - call: greet
+  - print: "after"
+  - call: greet
+greet:
+  - print: "hi"
+"#
+    ).unwrap();
+    let push_ptrs = exe.opcodes.iter().filter(|x| matches!(x, Instruction::PushPtr(_))).count();
+    assert_eq!(push_ptrs, 1);
+
+    let mut exec = Arc::new(exe.into_program("main").unwrap()).run();
+    let (messages, request) = run_until_input(&mut exec, Request::Resume);
+    assert_eq!(messages, vec!["hi", "after", "hi"]);
+    assert!(matches!(request, Request::Drop));
+}
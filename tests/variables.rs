@@ -0,0 +1,56 @@
+use texted_adventure::{ CompileErrorKind, Request, Value, compile_source };
+
+use std::sync::Arc;
+
+#[test]
+fn texts_show_the_variables_set_by_the_game() {
+    let exe = compile_source(
+        r#"
+extern: player_name
+main:
+  - print: "Hello, {player_name}!"
+"#
+    ).unwrap();
+    let mut exec = Arc::new(exe.into_program("main").unwrap()).run();
+    exec.set_variable("player_name", Value::Str("Bob".to_string()));
+    assert!(matches!(exec.unpause(None), Ok(Request::PrintMessage(x)) if x == "Hello, Bob!"));
+}
+
+#[test]
+fn texts_only_show_the_declared_variables() {
+    let err = compile_source("extern: [gold]\nmain:\n  - print: \"{gold} {silver}\"\n").err().unwrap();
+    assert!(matches!(err.kind, CompileErrorKind::UndeclaredVariable(x) if x == "silver"));
+}
+
+#[test]
+fn extern_takes_names_only() {
+    let err = compile_source("extern: [gold, 3]\nmain: []\n").err().unwrap();
+    assert!(matches!(err.kind, CompileErrorKind::BadExtern));
+    assert_eq!((err.pos.line, err.pos.col), (1, 16));
+}
+
+#[test]
+fn bare_words_are_variables() {
+    let err = compile_source("main:\n  - set: { name: Bob }\n").err().unwrap();
+    assert!(matches!(err.kind, CompileErrorKind::UndeclaredVariable(x) if x == "Bob"));
+    assert_eq!((err.pos.line, err.pos.col), (2, 5));
+
+    let exe = compile_source("main:\n  - set: { name: '\"Bob\"', greeting: \"'Hi, ' + name\" }\n").unwrap();
+    let mut exec = Arc::new(exe.into_program("main").unwrap()).run();
+    assert!(matches!(exec.unpause(None), Ok(Request::Drop)));
+    assert_eq!(exec.variables()["greeting"], Value::Str("Hi, Bob".to_string()));
+}
+
+#[test]
+fn conditions_only_read_the_declared_variables() {
+    let err = compile_source("main:\n  - if: rich\n    then: []\n").err().unwrap();
+    assert!(matches!(err.kind, CompileErrorKind::UndeclaredVariable(x) if x == "rich"));
+    let err = compile_source("main:\n  - choose:\n    - Buy: []\n      when: gold > 3\n").err().unwrap();
+    assert!(matches!(err.kind, CompileErrorKind::UndeclaredVariable(x) if x == "gold"));
+    assert_eq!((err.pos.line, err.pos.col), (3, 7));
+
+    let exe = compile_source("extern: rich\nmain:\n  - if: rich\n    then:\n      - print: \"Rich\"\n").unwrap();
+    let mut exec = Arc::new(exe.into_program("main").unwrap()).run();
+    exec.set_variable("rich", Value::Bool(true));
+    assert!(matches!(exec.unpause(None), Ok(Request::PrintMessage(x)) if x == "Rich"));
+}
@@ -0,0 +1,66 @@
+use texted_adventure::{ Request, Value, VmError };
+
+mod common;
+
+use common::start;
+
+// A faulty instruction reports the same error every time it's
+// retried, since it doesn't touch the stack
+fn assert_stuck(src : &str, expected : VmError) {
+    let mut exec = start(src);
+    let first = exec.unpause(None).err().unwrap();
+    assert_eq!(first, expected);
+    let before = exec.snapshot();
+    assert_eq!(exec.unpause(None).err().unwrap(), expected);
+    assert_eq!(exec.snapshot(), before);
+}
+
+#[test]
+fn bad_operands_stay_on_the_stack() {
+    assert_stuck("main: [{set: {x: 1 + true}}]", VmError::TypeMismatch(2));
+    assert_stuck("main: [{set: {x: -true}}]", VmError::TypeMismatch(1));
+    assert_stuck("main: [{if: 1, then: [{print: a}]}]", VmError::TypeMismatch(1));
+}
+
+#[test]
+fn only_numbers_divide_by_zero() {
+    assert_stuck("main: [{set: {x: 1 / 0}}]", VmError::DivisionByZero(2));
+    assert_stuck("main: [{set: {x: 1 % 0}}]", VmError::DivisionByZero(2));
+    assert_stuck("main: [{set: {x: \"'a' / 0\"}}]", VmError::TypeMismatch(2));
+}
+
+#[test]
+fn good_operands_are_replaced_with_the_result() {
+    let mut exec = start("main: [{set: {x: \"-(7 % 4) * 2\"}}, {if: x < 0, then: [{print: neg}]}]");
+    assert!(matches!(exec.unpause(None), Ok(Request::PrintMessage(x)) if x == "neg"));
+    assert!(exec.snapshot().value_stack.is_empty());
+    assert_eq!(exec.variables()["x"], Value::Int(-6));
+}
+
+#[test]
+fn overflow_names_the_private_dialogues() {
+    let mut exec = start(
+        r#"
+main:
+  - print: "start"
+  - call: ping
+  - print: "end"
+ping:
+  - call: pong
+  - print: "ping"
+pong:
+  - call: ping
+  - print: "pong"
+"#
+    );
+    exec.set_max_depth(Some(4));
+    assert!(matches!(exec.unpause(None), Ok(Request::PrintMessage(_))));
+    let chain = {
+        match exec.unpause(None) {
+            Err(VmError::StackOverflow { chain, .. }) => chain,
+            _ => panic!("expected a stack overflow"),
+        }
+    };
+    let names = chain.iter().map(|x| x.split('+').next().unwrap()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["main", "ping", "pong", "ping", "pong"]);
+}